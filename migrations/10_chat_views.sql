@@ -0,0 +1,7 @@
+-- Tweets seen in each day 19 chat room by each user, as last flushed.
+CREATE TABLE chat_views (
+  room INTEGER NOT NULL,
+  user_name TEXT NOT NULL,
+  views BIGINT NOT NULL CHECK (views >= 0),
+  PRIMARY KEY (room, user_name)
+);
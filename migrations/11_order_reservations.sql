@@ -0,0 +1,4 @@
+-- Stock each order took out of the catalog, given back when the order is
+-- replaced. Orders written without reserving stock, or before this column
+-- existed, reserved nothing.
+ALTER TABLE orders ADD COLUMN reserved INT NOT NULL DEFAULT 0 CHECK (reserved >= 0);
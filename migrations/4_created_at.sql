@@ -0,0 +1,5 @@
+-- When each order was placed. Orders from before this migration are dated to
+-- when it ran.
+ALTER TABLE orders ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX orders_created_at_idx ON orders (created_at);
@@ -0,0 +1,6 @@
+-- Regions form a tree, continents holding countries holding districts.
+ALTER TABLE regions
+  ADD COLUMN parent_id INT REFERENCES regions (id),
+  ADD CONSTRAINT regions_parent_id_check CHECK (parent_id <> id);
+
+CREATE INDEX regions_parent_id_idx ON regions (parent_id);
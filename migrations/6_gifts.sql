@@ -0,0 +1,7 @@
+-- Catalog of the gifts the warehouse keeps in stock. Orders still name their
+-- gift freely, the catalog only matters when they reserve stock.
+CREATE TABLE gifts (
+  name VARCHAR(50) PRIMARY KEY,
+  stock INT NOT NULL CHECK (stock >= 0),
+  low_stock_threshold INT NOT NULL DEFAULT 0 CHECK (low_stock_threshold >= 0)
+);
@@ -0,0 +1,10 @@
+-- Time capsules of day 12. They are stamped with the wall clock, so the time
+-- elapsed since they were saved survives restarts.
+CREATE TABLE capsules (
+  key VARCHAR(255) PRIMARY KEY,
+  saved_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ CHECK (expires_at > saved_at),
+  payload JSONB
+);
+
+CREATE INDEX capsules_expires_at_idx ON capsules (expires_at);
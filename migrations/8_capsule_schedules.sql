@@ -0,0 +1,19 @@
+-- Recurring deadlines and callbacks of day 12 capsules. Callbacks wait in
+-- their own table until delivered, so they outlive restarts and the capsule
+-- they were fired for.
+ALTER TABLE capsules
+  ADD COLUMN every_secs BIGINT CHECK (every_secs > 0),
+  ADD COLUMN callback TEXT,
+  ADD CHECK (every_secs IS NULL OR expires_at IS NOT NULL);
+
+CREATE TABLE capsule_callbacks (
+  id BIGSERIAL PRIMARY KEY,
+  key VARCHAR(255) NOT NULL,
+  url TEXT NOT NULL,
+  deadline TIMESTAMPTZ NOT NULL,
+  payload JSONB,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  due_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX capsule_callbacks_due_at_idx ON capsule_callbacks (due_at);
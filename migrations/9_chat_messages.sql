@@ -0,0 +1,11 @@
+-- History of the day 19 chat rooms, trimmed to the newest messages of each
+-- room as new ones come in.
+CREATE TABLE chat_messages (
+  id BIGSERIAL PRIMARY KEY,
+  room INTEGER NOT NULL,
+  user_name TEXT NOT NULL,
+  message TEXT NOT NULL,
+  sent_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX chat_messages_room_id_idx ON chat_messages (room, id);
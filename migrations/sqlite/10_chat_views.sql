@@ -0,0 +1,7 @@
+-- Mirrors the Postgres migration of the same name.
+CREATE TABLE chat_views (
+  room INTEGER NOT NULL,
+  user_name TEXT NOT NULL,
+  views INTEGER NOT NULL CHECK (views >= 0),
+  PRIMARY KEY (room, user_name)
+);
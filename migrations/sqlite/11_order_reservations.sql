@@ -0,0 +1,2 @@
+-- Mirrors the Postgres migration of the same name.
+ALTER TABLE orders ADD COLUMN reserved INTEGER NOT NULL DEFAULT 0 CHECK (reserved >= 0);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS orders (
+  id INT PRIMARY KEY,
+  region_id INT,
+  gift_name VARCHAR(50),
+  quantity INT
+);
@@ -0,0 +1,25 @@
+-- Mirrors the Postgres migration of the same name. SQLite cannot add
+-- constraints to existing tables, so both are rebuilt. Rows breaking the
+-- constraints fail the migration rather than being dropped.
+
+CREATE TABLE regions_new (
+  id INT PRIMARY KEY,
+  name VARCHAR(50) NOT NULL
+);
+INSERT INTO regions_new (id, name) SELECT id, name FROM regions;
+DROP TABLE regions;
+ALTER TABLE regions_new RENAME TO regions;
+
+CREATE TABLE orders_new (
+  id INT PRIMARY KEY,
+  region_id INT NOT NULL REFERENCES regions (id),
+  gift_name VARCHAR(50) NOT NULL,
+  quantity INT NOT NULL CHECK (quantity > 0)
+);
+INSERT INTO orders_new (id, region_id, gift_name, quantity)
+  SELECT id, region_id, gift_name, quantity FROM orders;
+DROP TABLE orders;
+ALTER TABLE orders_new RENAME TO orders;
+
+CREATE INDEX orders_region_id_idx ON orders (region_id);
+CREATE INDEX orders_gift_name_idx ON orders (gift_name);
@@ -0,0 +1,18 @@
+-- Mirrors the Postgres migration of the same name. SQLite cannot add a column
+-- defaulting to the current time, so the table is rebuilt.
+
+CREATE TABLE orders_new (
+  id INT PRIMARY KEY,
+  region_id INT NOT NULL REFERENCES regions (id),
+  gift_name VARCHAR(50) NOT NULL,
+  quantity INT NOT NULL CHECK (quantity > 0),
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+INSERT INTO orders_new (id, region_id, gift_name, quantity)
+  SELECT id, region_id, gift_name, quantity FROM orders;
+DROP TABLE orders;
+ALTER TABLE orders_new RENAME TO orders;
+
+CREATE INDEX orders_region_id_idx ON orders (region_id);
+CREATE INDEX orders_gift_name_idx ON orders (gift_name);
+CREATE INDEX orders_created_at_idx ON orders (created_at);
@@ -0,0 +1,5 @@
+-- Mirrors the Postgres migration of the same name.
+ALTER TABLE regions
+  ADD COLUMN parent_id INT REFERENCES regions (id) CHECK (parent_id <> id);
+
+CREATE INDEX regions_parent_id_idx ON regions (parent_id);
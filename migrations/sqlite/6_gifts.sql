@@ -0,0 +1,6 @@
+-- Mirrors the Postgres migration of the same name.
+CREATE TABLE gifts (
+  name VARCHAR(50) PRIMARY KEY,
+  stock INT NOT NULL CHECK (stock >= 0),
+  low_stock_threshold INT NOT NULL DEFAULT 0 CHECK (low_stock_threshold >= 0)
+);
@@ -0,0 +1,10 @@
+-- Mirrors the Postgres migration of the same name, the payload being JSON
+-- text.
+CREATE TABLE capsules (
+  key VARCHAR(255) PRIMARY KEY,
+  saved_at TEXT NOT NULL,
+  expires_at TEXT CHECK (julianday(expires_at) > julianday(saved_at)),
+  payload TEXT
+);
+
+CREATE INDEX capsules_expires_at_idx ON capsules (expires_at);
@@ -0,0 +1,16 @@
+-- Mirrors the Postgres migration of the same name. SQLite cannot add a table
+-- constraint, so a recurring capsule needing a deadline is left to the store.
+ALTER TABLE capsules ADD COLUMN every_secs INTEGER CHECK (every_secs > 0);
+ALTER TABLE capsules ADD COLUMN callback TEXT;
+
+CREATE TABLE capsule_callbacks (
+  id INTEGER PRIMARY KEY,
+  key VARCHAR(255) NOT NULL,
+  url TEXT NOT NULL,
+  deadline TEXT NOT NULL,
+  payload TEXT,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  due_at TEXT NOT NULL
+);
+
+CREATE INDEX capsule_callbacks_due_at_idx ON capsule_callbacks (due_at);
@@ -0,0 +1,10 @@
+-- Mirrors the Postgres migration of the same name.
+CREATE TABLE chat_messages (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  room INTEGER NOT NULL,
+  user_name TEXT NOT NULL,
+  message TEXT NOT NULL,
+  sent_at TEXT NOT NULL
+);
+
+CREATE INDEX chat_messages_room_id_idx ON chat_messages (room, id);
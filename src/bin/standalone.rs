@@ -0,0 +1,169 @@
+// Runs the calendar router without the Shuttle runtime.
+//
+// Usage: standalone [--bind <addr>] [--database-url <url>]
+//                   [--chat-rate-limit <per second>/<burst>|off]
+//
+// Falls back to the `BIND_ADDR`, `DATABASE_URL` and `CHAT_RATE_LIMIT`
+// environment variables, and to `0.0.0.0:8000` for the bind address. The
+// database URL selects the order store (`memory`, `sqlite:...` or
+// `postgres://...`) and defaults to `memory`. The chat rate limit caps the
+// frames each day 19 connection sends, `off` lifting it altogether.
+//
+// Day 19 rooms can only be moderated with the token in `CHAT_ADMIN_TOKEN`,
+// which has no flag so that it does not show up in the process list.
+
+use std::env;
+
+use anyhow::{anyhow, Context};
+use tokio::{net::TcpListener, signal, sync::oneshot};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[cfg(feature = "day19")]
+use cch23_santa5276::calendar::day19::RateLimit;
+#[cfg(feature = "db")]
+use cch23_santa5276::calendar::db;
+use cch23_santa5276::{app_with_workers, Options};
+
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:8000";
+
+struct Config {
+    bind_addr: String,
+    #[cfg_attr(not(feature = "db"), allow(dead_code))]
+    database_url: Option<String>,
+    options: Options,
+}
+
+impl Config {
+    fn from_env() -> anyhow::Result<Self> {
+        let mut bind_addr = env::var("BIND_ADDR").ok();
+        let mut database_url = env::var("DATABASE_URL").ok();
+        #[cfg(feature = "day19")]
+        let mut chat_rate_limit = env::var("CHAT_RATE_LIMIT").ok();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .with_context(|| format!("missing value for {arg}"))?;
+            match arg.as_str() {
+                "--bind" => bind_addr = Some(value),
+                "--database-url" => database_url = Some(value),
+                #[cfg(feature = "day19")]
+                "--chat-rate-limit" => chat_rate_limit = Some(value),
+                _ => return Err(anyhow!("unknown argument {arg}")),
+            }
+        }
+
+        #[cfg_attr(not(feature = "day19"), allow(unused_mut))]
+        let mut options = Options::default();
+        #[cfg(feature = "day19")]
+        if let Some(rate_limit) = chat_rate_limit {
+            options.chat.rate_limit = parse_rate_limit(&rate_limit)
+                .with_context(|| format!("invalid chat rate limit {rate_limit:?}"))?;
+        }
+        #[cfg(feature = "day19")]
+        {
+            options.chat.admin_token = env::var("CHAT_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty());
+        }
+
+        Ok(Self {
+            bind_addr: bind_addr.unwrap_or_else(|| DEFAULT_BIND_ADDR.to_string()),
+            database_url,
+            options,
+        })
+    }
+}
+
+// `<frames a second>/<burst>`, or `off` for no limit at all.
+#[cfg(feature = "day19")]
+fn parse_rate_limit(value: &str) -> anyhow::Result<Option<RateLimit>> {
+    if value == "off" {
+        return Ok(None);
+    }
+
+    let (per_second, burst) = value
+        .split_once('/')
+        .context("expected <per second>/<burst> or off")?;
+    let per_second = per_second.parse::<f64>()?;
+    let burst = burst.parse::<u32>()?;
+    if !(per_second.is_finite() && per_second > 0.0 && burst > 0) {
+        return Err(anyhow!("both the rate and the burst must be positive"));
+    }
+    Ok(Some(RateLimit { per_second, burst }))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = Config::from_env()?;
+
+    #[cfg(feature = "db")]
+    let (router, workers) = {
+        let database_url = config.database_url.as_deref().unwrap_or("memory");
+
+        let store = db::connect(database_url)
+            .await
+            .context("cannot open the order store")?;
+
+        app_with_workers(store, config.options)
+    };
+
+    #[cfg(not(feature = "db"))]
+    let (router, workers) = app_with_workers(config.options);
+
+    // Background work stops once the server has, so the last requests are
+    // still seen by it.
+    let (stop, stopped) = oneshot::channel::<()>();
+    let workers = tokio::spawn(workers.run(async {
+        let _ = stopped.await;
+    }));
+
+    let listener = TcpListener::bind(&config.bind_addr)
+        .await
+        .with_context(|| format!("cannot bind {}", config.bind_addr))?;
+    info!("listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    let _ = stop.send(());
+    workers.await?;
+
+    Ok(())
+}
+
+// Resolves on Ctrl+C or SIGTERM so in-flight requests can finish.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("shutting down");
+}
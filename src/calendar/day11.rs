@@ -13,12 +13,13 @@ pub fn task() -> Router {
 async fn red_pixels_route(mut multipart: Multipart) -> Result<String, AppError> {
     let mut red_pixel_count = 0;
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let data = field.bytes().await.unwrap();
+    while let Some(field) = multipart.next_field().await? {
+        let data = field.bytes().await?;
 
         let img: image::DynamicImage = ImageReader::new(Cursor::new(data))
             .with_guessed_format()?
-            .decode()?;
+            .decode()
+            .map_err(|e| AppError::bad_request(format!("cannot decode image: {e}")))?;
 
         red_pixel_count += img
             .to_rgb8()
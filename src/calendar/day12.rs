@@ -1,91 +1,887 @@
-use std::sync::RwLock;
+use std::{collections::HashMap, future::Future, net::IpAddr, pin::pin, time::Duration};
 
-use super::error::AppError;
 use axum::{
-    extract::{Path, State},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use chrono::{DateTime, Datelike, Utc};
-use serde_json::json;
-use std::time::Instant;
-use std::{collections::HashMap, sync::Arc};
-use tracing::info;
-use ulid::Ulid;
-use uuid::Uuid;
+use chrono::{DateTime, Datelike, NaiveDate, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use data_encoding::{BASE32, HEXLOWER_PERMISSIVE};
+use futures_util::future::join_all;
+use reqwest::{redirect, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::lookup_host, time::MissedTickBehavior};
+use tracing::{info, warn};
+use ulid::{Generator, Ulid};
+use uuid::{Builder, Uuid};
 
-type SharedState = Arc<std::sync::RwLock<AppState>>;
+use super::db::{Capsule, CapsuleCallback, Store};
+use super::error::AppError;
 
-#[derive(Default)]
-struct AppState {
-    state: HashMap<String, Instant>,
-}
+// Longest a capsule may be kept before it expires, about ten years.
+const MAX_TTL_SECS: i64 = 10 * 366 * 24 * 60 * 60;
+
+// Longest callback URL accepted.
+const MAX_CALLBACK_LEN: usize = 2048;
+
+// Most ULIDs minted by one request.
+const MAX_MINTED: usize = 1000;
 
-pub fn task() -> Router {
-    let shared_state = SharedState::default();
+// ULIDs hold their timestamp in 48 bits of milliseconds.
+const MAX_ULID_MILLIS: i64 = (1 << 48) - 1;
 
+// Callbacks are only delivered while a `Scheduler` runs next to the router.
+pub fn task(store: Store) -> Router {
     Router::new()
-        .route("/save/:data", post(save_state_route))
-        .route("/load/:data", get(load_state_route))
+        .route("/save/:key", post(save_route))
+        .route("/load/:key", get(load_route))
+        .route("/capsules", get(capsules_route))
+        .route(
+            "/capsules/:key",
+            get(capsule_route).delete(delete_capsule_route),
+        )
         .route("/ulids", post(ulids_route))
+        .route("/ulids/mint", post(ulids_mint_route))
+        .route("/ulids/convert", post(ulids_convert_route))
+        .route("/ulids/decode", post(ulids_decode_route))
         .route("/ulids/:weekday", post(ulids_weekday_route))
-        .with_state(shared_state)
+        .with_state(store)
+}
+
+#[derive(Deserialize, Debug)]
+struct SaveParams {
+    // Seconds until the capsule expires, kept for good without.
+    ttl: Option<i64>,
+    // When the capsule expires, instead of a ttl.
+    deadline: Option<DateTime<Utc>>,
+    // Seconds between deadlines of a capsule that starts over at each one
+    // rather than expiring. The first deadline defaults to one period ahead.
+    every: Option<i64>,
+    // URL POSTed to whenever a deadline passes.
+    callback: Option<String>,
+}
+
+// Capsule as sent to clients, with times relative to when it was asked for.
+#[derive(Serialize, Debug)]
+struct CapsuleView {
+    key: String,
+    // Whole seconds since the capsule was saved.
+    elapsed: i64,
+    // Seconds until the next deadline, rounded up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_in: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    every: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<Value>,
+}
+
+impl CapsuleView {
+    fn new(capsule: Capsule, now: DateTime<Utc>) -> Self {
+        Self {
+            elapsed: elapsed_secs(&capsule, now),
+            expires_in: remaining_secs(&capsule, now),
+            key: capsule.key,
+            every: capsule.every_secs,
+            callback: capsule.callback,
+            payload: capsule.payload,
+        }
+    }
+}
+
+// Saves a capsule under `key`, replacing the one saved there before. A JSON
+// body is kept as its payload.
+async fn save_route(
+    Path(key): Path<String>,
+    Query(params): Query<SaveParams>,
+    State(store): State<Store>,
+    body: Bytes,
+) -> Result<(), AppError> {
+    let saved_at = Utc::now();
+    let max_ttl = TimeDelta::seconds(MAX_TTL_SECS);
+    if params
+        .every
+        .is_some_and(|every| !(1..=MAX_TTL_SECS).contains(&every))
+    {
+        return Err(AppError::bad_request(format!(
+            "every must be between 1 and {MAX_TTL_SECS} seconds"
+        )));
+    }
+    let expires_at = match (params.ttl, params.deadline) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::bad_request(
+                "a capsule takes either a ttl or a deadline",
+            ))
+        }
+        (Some(ttl), None) if (1..=MAX_TTL_SECS).contains(&ttl) => {
+            Some(saved_at + TimeDelta::seconds(ttl))
+        }
+        (Some(_), None) => {
+            return Err(AppError::bad_request(format!(
+                "ttl must be between 1 and {MAX_TTL_SECS} seconds"
+            )))
+        }
+        (None, Some(deadline)) if deadline > saved_at && deadline - saved_at <= max_ttl => {
+            Some(deadline)
+        }
+        (None, Some(_)) => {
+            return Err(AppError::bad_request(format!(
+                "deadline must lie in the next {MAX_TTL_SECS} seconds"
+            )))
+        }
+        (None, None) => params
+            .every
+            .map(|every| saved_at + TimeDelta::seconds(every)),
+    };
+    if let Some(callback) = &params.callback {
+        check_callback(callback)?;
+        if expires_at.is_none() {
+            return Err(AppError::bad_request(
+                "a callback needs a ttl, a deadline or a period",
+            ));
+        }
+    }
+    let payload = if body.is_empty() {
+        None
+    } else {
+        let payload = serde_json::from_slice(&body)
+            .map_err(|err| AppError::bad_request(format!("payload is not valid JSON: {err}")))?;
+        Some(payload)
+    };
+
+    let capsule = Capsule {
+        key,
+        saved_at,
+        expires_at,
+        payload,
+        every_secs: params.every,
+        callback: params.callback,
+    };
+    if let Some(violation) = capsule.violation() {
+        return Err(AppError::bad_request(format!(
+            "capsule {:?} is invalid: {violation}",
+            capsule.key
+        )));
+    }
+    store.save_capsule(&capsule).await
+}
+
+#[derive(Deserialize)]
+struct LoadParams {
+    // Whether to report the time left until the next deadline rather than
+    // the time since saving.
+    #[serde(default)]
+    remaining: bool,
+}
+
+// Whole seconds since the capsule under `key` was saved, or with `remaining`
+// until its next deadline, rounded up.
+async fn load_route(
+    Path(key): Path<String>,
+    Query(params): Query<LoadParams>,
+    State(store): State<Store>,
+) -> Result<String, AppError> {
+    let now = Utc::now();
+    let capsule = store
+        .capsule(&key, now)
+        .await?
+        .ok_or_else(|| capsule_not_found(&key))?;
+
+    if !params.remaining {
+        return Ok(elapsed_secs(&capsule, now).to_string());
+    }
+    let secs = remaining_secs(&capsule, now)
+        .ok_or_else(|| AppError::conflict(format!("nothing saved under {key:?} has a deadline")))?;
+    Ok(secs.to_string())
+}
+
+async fn capsules_route(State(store): State<Store>) -> Result<impl IntoResponse, AppError> {
+    let now = Utc::now();
+    let capsules = store
+        .capsules(now)
+        .await?
+        .into_iter()
+        .map(|capsule| CapsuleView::new(capsule, now))
+        .collect::<Vec<_>>();
+
+    Ok(Json(capsules))
+}
+
+async fn capsule_route(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+) -> Result<impl IntoResponse, AppError> {
+    let now = Utc::now();
+    let capsule = store
+        .capsule(&key, now)
+        .await?
+        .ok_or_else(|| capsule_not_found(&key))?;
+
+    Ok(Json(CapsuleView::new(capsule, now)))
+}
+
+async fn delete_capsule_route(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+) -> Result<StatusCode, AppError> {
+    if !store.delete_capsule(&key, Utc::now()).await? {
+        return Err(capsule_not_found(&key));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Seconds until the next deadline, rounded up.
+fn remaining_secs(capsule: &Capsule, now: DateTime<Utc>) -> Option<i64> {
+    capsule.deadline(now).map(|deadline| {
+        let left = (deadline - now).num_milliseconds();
+        (left + 999).div_euclid(1000)
+    })
+}
+
+fn check_callback(callback: &str) -> Result<(), AppError> {
+    let url = Url::parse(callback)
+        .map_err(|err| AppError::bad_request(format!("callback is not a valid URL: {err}")))?;
+    if !matches!(url.scheme(), "http" | "https") || callback.len() > MAX_CALLBACK_LEN {
+        return Err(AppError::bad_request(format!(
+            "callback must be an http or https URL of at most {MAX_CALLBACK_LEN} characters"
+        )));
+    }
+    Ok(())
 }
 
-async fn save_state_route(Path(key): Path<String>, State(state): State<Arc<RwLock<AppState>>>) {
-    let state = &mut state.write().unwrap().state;
-    state.insert(key, Instant::now());
+fn elapsed_secs(capsule: &Capsule, now: DateTime<Utc>) -> i64 {
+    // A clock set back since saving must not make time run backwards.
+    (now - capsule.saved_at).num_seconds().max(0)
 }
 
-async fn load_state_route(Path(id): Path<String>, State(state): State<SharedState>) -> String {
-    let state_info = &state.read().unwrap().state;
-    let time = state_info.get(&id).unwrap();
-    (*time).elapsed().as_secs().to_string()
+fn capsule_not_found(key: &str) -> AppError {
+    AppError::not_found(format!("nothing saved under {key:?}"))
 }
 
-async fn ulids_route(data: Json<Vec<String>>) -> Json<Vec<String>> {
-    let ids: Vec<String> = data
+fn parse_ulid(id: &str) -> Result<Ulid, AppError> {
+    Ulid::from_string(id).map_err(|e| AppError::bad_request(format!("invalid ULID {id:?}: {e}")))
+}
+
+async fn ulids_route(data: Json<Vec<String>>) -> Result<Json<Vec<String>>, AppError> {
+    let ids = data
         .iter()
-        .map(|id| Uuid::from(Ulid::from_string(id).unwrap()).to_string())
+        .map(|id| parse_ulid(id).map(|ulid| Uuid::from(ulid).to_string()))
         .rev()
-        .collect();
-    Json(ids)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(ids))
+}
+
+#[derive(Deserialize, Debug)]
+struct WeekdayParams {
+    // IANA time zone the dates are evaluated in, UTC without.
+    tz: Option<String>,
+    // Predicates a ULID has to pass all of to count as matching.
+    month: Option<u32>,
+    day: Option<u32>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    // Comma separated, by name or by number from Monday as 0.
+    weekdays: Option<String>,
+    histogram: Option<Bucket>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Bucket {
+    Day,
+    Hour,
+}
+
+impl Bucket {
+    // Local day or hour of `date`.
+    fn key(self, date: &DateTime<Tz>) -> String {
+        match self {
+            Self::Day => date.format("%Y-%m-%d").to_string(),
+            // The offset tells apart the hours repeated when clocks go back.
+            Self::Hour => date.format("%Y-%m-%dT%H:00%:z").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DateFilter {
+    month: Option<u32>,
+    day: Option<u32>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    weekdays: Option<Vec<Weekday>>,
+}
+
+impl DateFilter {
+    fn new(params: &WeekdayParams) -> Result<Option<Self>, AppError> {
+        if params.month.is_some_and(|month| !(1..=12).contains(&month)) {
+            return Err(AppError::bad_request("month must be between 1 and 12"));
+        }
+        if params.day.is_some_and(|day| !(1..=31).contains(&day)) {
+            return Err(AppError::bad_request("day must be between 1 and 31"));
+        }
+        if let (Some(from), Some(to)) = (params.from, params.to) {
+            if from > to {
+                return Err(AppError::bad_request("from must not be after to"));
+            }
+        }
+        let weekdays = params
+            .weekdays
+            .as_deref()
+            .map(|weekdays| weekdays.split(',').map(parse_weekday).collect())
+            .transpose()?;
+
+        let filter = Self {
+            month: params.month,
+            day: params.day,
+            from: params.from,
+            to: params.to,
+            weekdays,
+        };
+        let is_empty = filter.month.is_none()
+            && filter.day.is_none()
+            && filter.from.is_none()
+            && filter.to.is_none()
+            && filter.weekdays.is_none();
+        Ok((!is_empty).then_some(filter))
+    }
+
+    fn matches(&self, date: NaiveDate) -> bool {
+        self.month.is_none_or(|month| date.month() == month)
+            && self.day.is_none_or(|day| date.day() == day)
+            && self.from.is_none_or(|from| date >= from)
+            && self.to.is_none_or(|to| date <= to)
+            && self
+                .weekdays
+                .as_ref()
+                .is_none_or(|weekdays| weekdays.contains(&date.weekday()))
+    }
+}
+
+fn parse_weekday(weekday: &str) -> Result<Weekday, AppError> {
+    let weekday = weekday.trim();
+    match weekday.parse::<u8>() {
+        Ok(number) => Weekday::try_from(number).ok(),
+        Err(_) => weekday.parse().ok(),
+    }
+    .ok_or_else(|| AppError::bad_request(format!("unknown weekday {weekday:?}")))
+}
+
+#[derive(Serialize, Debug, Default)]
+struct WeekdayCounts {
+    #[serde(rename = "christmas eve")]
+    christmas_eve: usize,
+    weekday: usize,
+    #[serde(rename = "in the future")]
+    in_the_future: usize,
+    #[serde(rename = "LSB is 1")]
+    lsb_is_1: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matching: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    histogram: Option<Vec<HistogramBar>>,
+}
+
+#[derive(Serialize, Debug)]
+struct HistogramBar {
+    bucket: String,
+    count: usize,
 }
 
 async fn ulids_weekday_route(
     Path(weekday): Path<u32>,
+    Query(params): Query<WeekdayParams>,
     data: Json<Vec<String>>,
-) -> Result<impl IntoResponse, AppError> {
-    let mut christmas_eve_count = 0;
-    let mut weekday_count = 0;
-    let mut future_day_count = 0;
-    let mut lsb_count = 0;
+) -> Result<Json<WeekdayCounts>, AppError> {
+    let tz = match &params.tz {
+        Some(tz) => tz
+            .parse::<Tz>()
+            .map_err(|_| AppError::bad_request(format!("unknown time zone {tz:?}")))?,
+        None => Tz::UTC,
+    };
+    let filter = DateFilter::new(&params)?;
 
-    let dates: Vec<DateTime<Utc>> = data
+    let ulids = data
         .iter()
-        .map(|id| Ulid::from_string(id).unwrap())
-        .inspect(|ulid| lsb_count += (ulid.0 & 1) as u32)
-        .map(|ulid| DateTime::<Utc>::from(ulid.datetime()))
-        .collect();
+        .map(|id| parse_ulid(id))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    for date in dates {
+    let now = Utc::now();
+    let mut counts = WeekdayCounts {
+        matching: filter.as_ref().map(|_| 0),
+        ..Default::default()
+    };
+    // Count per bucket, by the earliest ULID in the bucket.
+    let mut histogram = HashMap::<String, (DateTime<Tz>, usize)>::new();
+    for ulid in ulids {
+        let date = DateTime::<Utc>::from(ulid.datetime()).with_timezone(&tz);
         info!("{:?}", date);
         if date.month() == 12 && date.day() == 24 {
-            christmas_eve_count += 1;
+            counts.christmas_eve += 1;
         }
         if date.weekday().num_days_from_monday() == weekday {
-            weekday_count += 1;
+            counts.weekday += 1;
         }
-        if date > Utc::now() {
-            future_day_count += 1;
+        if date > now {
+            counts.in_the_future += 1;
         }
+        if ulid.0 & 1 == 1 {
+            counts.lsb_is_1 += 1;
+        }
+
+        // The histogram only counts the ULIDs passing the predicates.
+        if filter
+            .as_ref()
+            .is_some_and(|filter| !filter.matches(date.date_naive()))
+        {
+            continue;
+        }
+        if let Some(matching) = &mut counts.matching {
+            *matching += 1;
+        }
+        if let Some(bucket) = params.histogram {
+            let (earliest, count) = histogram.entry(bucket.key(&date)).or_insert((date, 0));
+            *earliest = date.min(*earliest);
+            *count += 1;
+        }
+    }
+
+    if params.histogram.is_some() {
+        let mut bars = histogram.into_iter().collect::<Vec<_>>();
+        bars.sort_by_key(|(_, (earliest, _))| *earliest);
+        counts.histogram = Some(
+            bars.into_iter()
+                .map(|(bucket, (_, count))| HistogramBar { bucket, count })
+                .collect(),
+        );
+    }
+
+    Ok(Json(counts))
+}
+
+#[derive(Deserialize, Debug)]
+struct MintParams {
+    count: Option<usize>,
+    // Time the ULIDs are minted for, now without.
+    timestamp: Option<DateTime<Utc>>,
+}
+
+// Mints `count` ULIDs, each one larger than the one before even within the
+// same millisecond.
+async fn ulids_mint_route(Query(params): Query<MintParams>) -> Result<Json<Vec<String>>, AppError> {
+    let count = params.count.unwrap_or(1);
+    if !(1..=MAX_MINTED).contains(&count) {
+        return Err(AppError::bad_request(format!(
+            "count must be between 1 and {MAX_MINTED}"
+        )));
+    }
+    let timestamp = params.timestamp.unwrap_or_else(Utc::now);
+    if !(0..=MAX_ULID_MILLIS).contains(&timestamp.timestamp_millis()) {
+        return Err(AppError::bad_request(
+            "timestamp must lie between 1970 and the year 10889",
+        ));
+    }
+
+    // A generator of its own, since a shared one would hold on to the newest
+    // time it minted for and ignore older timestamps.
+    let mut generator = Generator::new();
+    let ulids = (0..count)
+        .map(|_| {
+            generator
+                .generate_from_datetime(timestamp.into())
+                .map(|ulid| ulid.to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(ulids))
+}
+
+// Text forms an id can take. Which one an id is in is told by its shape.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum IdFormat {
+    // Crockford base32, 26 characters.
+    Ulid,
+    // Hyphenated UUID, keeping every bit of the ULID.
+    Uuid,
+    // UUIDv4, only when converting to it: 6 bits of the ULID make room for
+    // the version and variant, and the timestamp no longer means anything.
+    UuidV4,
+    // UUIDv7, only when converting to it: the timestamp is kept and 6 bits of
+    // randomness make room for the version and variant.
+    UuidV7,
+    // 32 hex digits.
+    Hex,
+    // RFC 4648 base32 with padding, 32 characters.
+    Base32,
+}
+
+impl IdFormat {
+    fn parse(id: &str) -> Result<(Ulid, Self), String> {
+        let (ulid, format) = match id.len() {
+            // 26 base32 digits hold 130 bits, a leading digit above 7 overflows.
+            26 if id.as_bytes()[0].is_ascii_alphanumeric() && id.as_bytes()[0] > b'7' => {
+                return Err("ULID does not fit 128 bits".to_string())
+            }
+            26 => (
+                Ulid::from_string(id).map_err(|err| format!("invalid ULID: {err}"))?,
+                Self::Ulid,
+            ),
+            36 => {
+                let uuid = Uuid::try_parse(id).map_err(|err| format!("invalid UUID: {err}"))?;
+                (Ulid::from(uuid), Self::Uuid)
+            }
+            32 if id.ends_with('=') => {
+                let bytes = BASE32
+                    .decode(id.to_ascii_uppercase().as_bytes())
+                    .map_err(|err| format!("invalid base32: {err}"))?;
+                (Ulid::from_bytes(to_array(&bytes)?), Self::Base32)
+            }
+            32 => {
+                let bytes = HEXLOWER_PERMISSIVE
+                    .decode(id.as_bytes())
+                    .map_err(|err| format!("invalid hex: {err}"))?;
+                (Ulid::from_bytes(to_array(&bytes)?), Self::Hex)
+            }
+            _ => {
+                return Err(
+                    "expected a ULID, a UUID, 32 hex digits or 32 characters of base32".to_string(),
+                )
+            }
+        };
+
+        Ok((ulid, format))
     }
 
-    Ok(Json(json!({
-        "christmas eve": christmas_eve_count,
-        "weekday": weekday_count,
-        "in the future": future_day_count,
-        "LSB is 1": lsb_count,
-    })))
+    fn format(self, ulid: Ulid) -> String {
+        match self {
+            Self::Ulid => ulid.to_string(),
+            Self::Uuid => Uuid::from(ulid).to_string(),
+            Self::UuidV4 => Builder::from_random_bytes(ulid.to_bytes())
+                .into_uuid()
+                .to_string(),
+            Self::UuidV7 => {
+                let random = ulid.to_bytes()[6..].try_into().unwrap();
+                Builder::from_unix_timestamp_millis(ulid.timestamp_ms(), &random)
+                    .into_uuid()
+                    .to_string()
+            }
+            Self::Hex => HEXLOWER_PERMISSIVE.encode(&ulid.to_bytes()),
+            Self::Base32 => BASE32.encode(&ulid.to_bytes()),
+        }
+    }
+}
+
+fn to_array(bytes: &[u8]) -> Result<[u8; 16], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("expected 16 bytes, found {}", bytes.len()))
+}
+
+// Outcome for one id of a batch, a malformed id failing on its own.
+#[derive(Serialize, Debug)]
+struct Item<T> {
+    input: String,
+    #[serde(flatten)]
+    result: ItemResult<T>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum ItemResult<T> {
+    Ok(T),
+    Err { error: String },
+}
+
+impl<T> Item<T> {
+    fn new(input: String, result: Result<T, String>) -> Self {
+        let result = match result {
+            Ok(value) => ItemResult::Ok(value),
+            Err(error) => ItemResult::Err { error },
+        };
+        Self { input, result }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ConvertParams {
+    to: IdFormat,
+}
+
+#[derive(Serialize, Debug)]
+struct Converted {
+    output: String,
+}
+
+// Converts every id, whatever form it is in, to the form `to` asks for.
+async fn ulids_convert_route(
+    Query(params): Query<ConvertParams>,
+    Json(ids): Json<Vec<String>>,
+) -> Json<Vec<Item<Converted>>> {
+    let items = ids
+        .into_iter()
+        .map(|id| {
+            let converted = IdFormat::parse(&id).map(|(ulid, _)| Converted {
+                output: params.to.format(ulid),
+            });
+            Item::new(id, converted)
+        })
+        .collect();
+
+    Json(items)
+}
+
+#[derive(Serialize, Debug)]
+struct Decoded {
+    format: IdFormat,
+    // Version of an id given as UUID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid_version: Option<usize>,
+    ulid: String,
+    timestamp: DateTime<Utc>,
+    timestamp_ms: u64,
+    // The 80 bits of randomness as hex.
+    randomness: String,
+}
+
+// Splits every id into the timestamp and randomness a ULID is made of.
+async fn ulids_decode_route(Json(ids): Json<Vec<String>>) -> Json<Vec<Item<Decoded>>> {
+    let items = ids
+        .into_iter()
+        .map(|id| {
+            let decoded = IdFormat::parse(&id).map(|(ulid, format)| Decoded {
+                format,
+                uuid_version: (format == IdFormat::Uuid)
+                    .then(|| Uuid::from(ulid).get_version_num()),
+                ulid: ulid.to_string(),
+                timestamp: DateTime::from(ulid.datetime()),
+                timestamp_ms: ulid.timestamp_ms(),
+                randomness: HEXLOWER_PERMISSIVE.encode(&ulid.to_bytes()[6..]),
+            });
+            Item::new(id, decoded)
+        })
+        .collect();
+
+    Json(items)
+}
+
+// Delivery of capsule callbacks by a task polling the store. Callbacks are
+// POSTed as JSON and retried with exponential backoff until the receiver
+// answers with a success status. Redirects are not followed, and callbacks
+// to hosts that are not on the public internet are given up on.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub poll_interval: Duration,
+    // Delay before the first retry, doubled for every one after.
+    pub retry_delay: Duration,
+    pub max_attempts: i32,
+    pub timeout: Duration,
+    // Hosts called back whatever their addresses are, like a receiver on the
+    // same machine.
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            retry_delay: Duration::from_secs(5),
+            max_attempts: 5,
+            timeout: Duration::from_secs(10),
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+// Why a callback was not delivered.
+enum DeliveryError {
+    // The receiver may still answer when tried again.
+    Failed(String),
+    // The callback goes somewhere it must not.
+    Refused(String),
+}
+
+// Callbacks delivered at once.
+const CALLBACK_BATCH: i64 = 100;
+
+impl Scheduler {
+    // Delivers due callbacks until `shutdown` resolves, finishing the
+    // deliveries under way first.
+    pub async fn run(self, store: Store, shutdown: impl Future<Output = ()>) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut shutdown = pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = &mut shutdown => return,
+            }
+            if let Err(err) = self.tick(&store).await {
+                warn!("cannot deliver capsule callbacks: {err:?}");
+            }
+        }
+    }
+
+    async fn tick(&self, store: &Store) -> Result<(), AppError> {
+        let now = Utc::now();
+        store.fire_capsules(now).await?;
+
+        // A delivery cut short by a restart is tried again once its lease
+        // runs out.
+        let lease_until = now + self.timeout * 2;
+        let callbacks = store
+            .claim_callbacks(now, lease_until, CALLBACK_BATCH)
+            .await?;
+        let deliveries = callbacks
+            .into_iter()
+            .map(|callback| self.deliver(store, callback));
+        for result in join_all(deliveries).await {
+            result?;
+        }
+
+        Ok(())
+    }
+
+    async fn deliver(&self, store: &Store, callback: CapsuleCallback) -> Result<(), AppError> {
+        let body = json!({
+            "key": callback.key,
+            "deadline": callback.deadline,
+            "attempt": callback.attempts,
+            "payload": callback.payload,
+        });
+        let result = match self.client_for(&callback.url).await {
+            Ok(client) => match client.post(&callback.url).json(&body).send().await {
+                // Redirects are not followed, so they do not count either.
+                Ok(response) if response.status().is_success() => Ok(()),
+                Ok(response) => Err(DeliveryError::Failed(format!(
+                    "receiver answered {}",
+                    response.status()
+                ))),
+                Err(err) => Err(DeliveryError::Failed(err.without_url().to_string())),
+            },
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => store.finish_callback(callback.id).await,
+            Err(DeliveryError::Refused(err)) => {
+                warn!("refusing the callback of capsule {:?}: {err}", callback.key);
+                store.finish_callback(callback.id).await
+            }
+            Err(DeliveryError::Failed(err)) if callback.attempts >= self.max_attempts => {
+                warn!(
+                    "giving up on the callback of capsule {:?} after {} attempts: {err}",
+                    callback.key, callback.attempts
+                );
+                store.finish_callback(callback.id).await
+            }
+            Err(DeliveryError::Failed(err)) => {
+                info!(
+                    "callback of capsule {:?} failed, retrying: {err}",
+                    callback.key
+                );
+                let backoff = self
+                    .retry_delay
+                    .saturating_mul(2u32.saturating_pow(callback.attempts as u32 - 1));
+                store
+                    .retry_callback(callback.id, Utc::now() + backoff)
+                    .await
+            }
+        }
+    }
+
+    // Client for a single delivery to `url`. Unless the host is allowed, it
+    // must only have public addresses, and the client connects to no other,
+    // so the host cannot point somewhere else once checked.
+    async fn client_for(&self, url: &str) -> Result<reqwest::Client, DeliveryError> {
+        let url =
+            Url::parse(url).map_err(|err| DeliveryError::Refused(format!("invalid URL: {err}")))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| DeliveryError::Refused("URL without a host".to_string()))?;
+        let builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(redirect::Policy::none());
+
+        let allowed = self
+            .allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host));
+        let builder = if allowed {
+            builder
+        } else if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+            check_public(host, ip)?;
+            builder
+        } else {
+            let port = url.port_or_known_default().unwrap_or(80);
+            let addrs = lookup_host((host, port))
+                .await
+                .map_err(|err| DeliveryError::Failed(format!("cannot resolve {host}: {err}")))?
+                .collect::<Vec<_>>();
+            for addr in &addrs {
+                check_public(host, addr.ip())?;
+            }
+            builder.resolve_to_addrs(host, &addrs)
+        };
+
+        builder
+            .build()
+            .map_err(|err| DeliveryError::Failed(format!("cannot build a client: {err}")))
+    }
+}
+
+fn check_public(host: &str, ip: IpAddr) -> Result<(), DeliveryError> {
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(DeliveryError::Refused(format!(
+            "{host} is not a public address ({ip})"
+        )))
+    }
+}
+
+// Whether `ip` is on the public internet rather than, say, the network the
+// server runs in or a cloud metadata service.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This network", shared address space, IETF protocol
+                // assignments, benchmarking and reserved.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let [a, b, ..] = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, link-local, documentation and NAT64.
+                || (a & 0xfe00) == 0xfc00
+                || (a & 0xffc0) == 0xfe80
+                || (a == 0x2001 && b == 0x0db8)
+                || (a == 0x0064 && b == 0xff9b))
+        }
+    }
 }
@@ -1,94 +1,219 @@
 use axum::{
-    extract::State,
+    extract::{DefaultBodyLimit, Query, State},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
 
-use super::db::{Order, Pool};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::db::{
+    Bucket, IngestParams, Order, PopularQuery, Store, Ties, TimeRange, MAX_BATCH_BYTES,
+};
 use super::error::AppError;
+use super::ingest::Batch;
+
+// Points a time series may hold once empty buckets are filled in.
+const MAX_SERIES_POINTS: usize = 10_000;
+
+// Most gifts a popularity ranking may be asked for, before ties.
+const MAX_RANKED_GIFTS: i64 = 1000;
+
+// Most rows a playground statement may return, also the default.
+const MAX_SELECT_ROWS: usize = 1000;
 
-pub fn task(pool: Pool) -> Router {
+pub fn task(store: Store) -> Router {
     Router::new()
-        .route("/sql", get(sql_route))
+        .route("/sql", get(sql_route).post(sql_select_route))
         .route("/reset", post(reset_route))
         .route("/orders", post(orders_route))
         .route("/orders/total", get(orders_total_route))
         .route("/orders/popular", get(orders_popular_route))
-        .with_state(pool)
+        .layer(DefaultBodyLimit::max(MAX_BATCH_BYTES))
+        .with_state(store)
 }
 
-async fn sql_route(State(pool): State<Pool>) -> Result<String, AppError> {
-    let number = sqlx::query!("SELECT 20231213 number")
-        .fetch_one(&pool.pool)
-        .await?
-        .number
-        .unwrap();
+async fn sql_route(State(store): State<Store>) -> Result<String, AppError> {
+    let number = store.ping(20231213).await?;
 
     Ok(number.to_string())
 }
 
-async fn reset_route(State(pool): State<Pool>) -> Result<(), AppError> {
-    sqlx::query!("DROP TABLE IF EXISTS orders")
-        .execute(&pool.pool)
-        .await?;
-
-    sqlx::query!(
-        "CREATE TABLE orders (
-              id INT PRIMARY KEY,
-              region_id INT,
-              gift_name VARCHAR(50),
-              quantity INT
-        )"
-    )
-    .execute(&pool.pool)
-    .await?;
-
-    Ok(())
+async fn sql_select_route(
+    State(store): State<Store>,
+    Json(params): Json<SelectParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let max_rows = params.max_rows.unwrap_or(MAX_SELECT_ROWS);
+    if !(1..=MAX_SELECT_ROWS).contains(&max_rows) {
+        return Err(AppError::bad_request(format!(
+            "max_rows must be between 1 and {MAX_SELECT_ROWS}"
+        )));
+    }
+
+    let output = store.run_select(&params.query, max_rows).await?;
+
+    Ok(Json(output))
+}
+
+async fn reset_route(State(store): State<Store>) -> Result<(), AppError> {
+    store.reset_orders().await
 }
 
 async fn orders_route(
-    State(pool): State<Pool>,
-    Json(data): Json<Vec<Order>>,
-) -> Result<(), AppError> {
-    for order in data {
-        sqlx::query!(
-            "INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)",
-            order.id,
-            order.region_id,
-            order.gift_name,
-            order.quantity,
-        )
-        .execute(&pool.pool)
-        .await?;
+    State(store): State<Store>,
+    Query(mut params): Query<IngestParams>,
+    Batch(data): Batch<Order>,
+) -> Result<impl IntoResponse, AppError> {
+    // Day 13 has no route for regions, so its orders bring their own.
+    params.create_regions = true;
+    let report = store.insert_orders(&data, &params).await?;
+
+    Ok(Json(report))
+}
+
+async fn orders_total_route(
+    State(store): State<Store>,
+    Query(params): Query<WindowParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let range = params.range()?;
+    let Some(bucket) = params.bucket else {
+        let total = store.total_quantity(&range).await?;
+        return Ok(Json(serde_json::json!({"total": total})));
+    };
+
+    let series = store.total_series(&range, bucket).await?;
+    let series = fill_gaps(&range, bucket, series, 0)?
+        .into_iter()
+        .map(|(start, total)| serde_json::json!({"start": start, "total": total}))
+        .collect::<Vec<_>>();
+
+    Ok(Json(
+        serde_json::json!({"bucket": bucket, "series": series}),
+    ))
+}
+
+async fn orders_popular_route(
+    State(store): State<Store>,
+    Query(window): Query<WindowParams>,
+    Query(params): Query<RankingParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let range = window.range()?;
+    if let Some(bucket) = window.bucket {
+        if params.n.is_some() || params.ties.is_some() {
+            return Err(AppError::bad_request(
+                "n and ties cannot be combined with bucket",
+            ));
+        }
+
+        let series = store
+            .popular_series(&range, params.region_id, bucket)
+            .await?
+            .into_iter()
+            .map(|(start, popular)| (start, Some(popular)))
+            .collect();
+        let series = fill_gaps(&range, bucket, series, None)?
+            .into_iter()
+            .map(|(start, popular)| serde_json::json!({"start": start, "popular": popular}))
+            .collect::<Vec<_>>();
+
+        return Ok(Json(
+            serde_json::json!({"bucket": bucket, "series": series}),
+        ));
     }
-    Ok(())
+
+    let limit = params.n.unwrap_or(1);
+    if !(1..=MAX_RANKED_GIFTS).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "n must be between 1 and {MAX_RANKED_GIFTS}"
+        )));
+    }
+    let query = PopularQuery {
+        range,
+        region_id: params.region_id,
+        limit,
+        ties: params.ties.unwrap_or_default(),
+    };
+    let gifts = store.popular_gifts(&query).await?;
+
+    // Without n or ties, answer with the single most popular gift as before.
+    if params.n.is_none() && params.ties.is_none() {
+        let popular = gifts.into_iter().next().map(|gift| gift.gift);
+        return Ok(Json(serde_json::json!({"popular": popular})));
+    }
+
+    Ok(Json(serde_json::json!({"gifts": gifts})))
+}
+
+#[derive(Deserialize, Debug)]
+struct SelectParams {
+    query: String,
+    max_rows: Option<usize>,
 }
 
-async fn orders_total_route(State(pool): State<Pool>) -> Result<impl IntoResponse, AppError> {
-    if let Ok(record) = sqlx::query!(r#"SELECT SUM(quantity) as "total!" FROM orders"#)
-        .fetch_one(&pool.pool)
-        .await
-    {
-        Ok(Json(serde_json::json!({"total": record.total})))
-    } else {
-        Ok(Json(serde_json::json!({"total": 0})))
+#[derive(Deserialize, Debug)]
+struct WindowParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    bucket: Option<Bucket>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RankingParams {
+    region_id: Option<i32>,
+    n: Option<i64>,
+    ties: Option<Ties>,
+}
+
+impl WindowParams {
+    fn range(&self) -> Result<TimeRange, AppError> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                return Err(AppError::bad_request("from has to be before to"));
+            }
+        }
+
+        Ok(TimeRange {
+            from: self.from,
+            to: self.to,
+        })
     }
 }
 
-async fn orders_popular_route(State(pool): State<Pool>) -> impl IntoResponse {
-    if let Ok(record) = sqlx::query!(
-        r#"SELECT gift_name as "popular!", SUM(quantity) AS gift_count
-          FROM orders
-          GROUP BY gift_name
-          ORDER BY gift_count DESC
-          LIMIT 1"#
-    )
-    .fetch_one(&pool.pool)
-    .await
-    {
-        Json(serde_json::json!({"popular": record.popular}))
-    } else {
-        Json(serde_json::json!({"popular": null}))
+// Adds an `empty` point for every bucket without orders, from the one `from`
+// falls in (or the first with orders) up to `to` (or the last with orders).
+fn fill_gaps<T: Clone>(
+    range: &TimeRange,
+    bucket: Bucket,
+    series: Vec<(DateTime<Utc>, T)>,
+    empty: T,
+) -> Result<Vec<(DateTime<Utc>, T)>, AppError> {
+    let first = range
+        .from
+        .map(|from| bucket.start(from))
+        .or_else(|| series.first().map(|(start, _)| *start));
+    let end = range
+        .to
+        .or_else(|| series.last().map(|(start, _)| bucket.next(*start)));
+    let (Some(mut start), Some(end)) = (first, end) else {
+        return Ok(series);
+    };
+
+    let mut points = series.into_iter().peekable();
+    let mut filled = Vec::new();
+    while start < end {
+        if filled.len() == MAX_SERIES_POINTS {
+            return Err(AppError::bad_request(format!(
+                "the range spans more than {MAX_SERIES_POINTS} buckets, narrow it or pick a larger bucket"
+            )));
+        }
+
+        let point = points
+            .next_if(|(point_start, _)| *point_start == start)
+            .unwrap_or_else(|| (start, empty.clone()));
+        filled.push(point);
+        start = bucket.next(start);
     }
+
+    Ok(filled)
 }
@@ -91,8 +91,8 @@ async fn game_route(Json(body): Json<Nice>) -> impl IntoResponse {
         .expect("not valid regex")
         .captures_iter(&input)
         .map(|c| c.extract::<0>().0)
-        .map(|s| s.parse::<i32>().expect("parse error"))
-        .sum::<i32>()
+        .map(|s| s.parse::<u64>().unwrap_or(u64::MAX))
+        .fold(0u64, u64::saturating_add)
         != 2023
     {
         return (
@@ -1,128 +1,385 @@
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use super::db::{Order, Pool, Region};
+use super::db::{
+    AnalyticsQuery, Direction, Gift, IngestParams, Order, Region, RegionNode, SortKey, Store,
+    MAX_BATCH_BYTES,
+};
 use super::error::AppError;
+use super::export::{Columns, Format};
+use super::ingest::Batch;
 
-pub fn task(pool: Pool) -> Router {
+pub fn task(store: Store) -> Router {
     Router::new()
         .route("/reset", post(reset_route))
         .route("/orders", post(orders_route))
         .route("/regions", post(regions_route))
         .route("/regions/total", get(regions_total_route))
         .route("/regions/top_list/:num", get(regions_toplist_route))
-        .with_state(pool)
-}
-
-async fn reset_route(State(pool): State<Pool>) -> Result<(), AppError> {
-    sqlx::query!("drop table if exists regions")
-        .execute(&pool.pool)
-        .await?;
-
-    sqlx::query!("drop table if exists orders")
-        .execute(&pool.pool)
-        .await?;
-
-    sqlx::query!(
-        "create table regions (
-          id INT PRIMARY KEY,
-          name VARCHAR(50)
-      )"
-    )
-    .execute(&pool.pool)
-    .await?;
-    sqlx::query!(
-        "create table orders (
-          id INT PRIMARY KEY,
-          region_id INT,
-          gift_name VARCHAR(50),
-          quantity INT
-      )"
-    )
-    .execute(&pool.pool)
-    .await?;
-    Ok(())
+        .route("/analytics", get(analytics_route))
+        .route("/export/orders", get(export_orders_route))
+        .route("/gifts", get(gifts_route).post(create_gift_route))
+        .route("/gifts/low_stock", get(low_stock_route))
+        .route(
+            "/gifts/:name",
+            get(gift_route)
+                .put(put_gift_route)
+                .delete(delete_gift_route),
+        )
+        .layer(DefaultBodyLimit::max(MAX_BATCH_BYTES))
+        .with_state(store)
+}
+
+async fn reset_route(State(store): State<Store>) -> Result<(), AppError> {
+    store.reset_all().await
 }
 
 async fn orders_route(
-    State(pool): State<Pool>,
-    Json(orders): Json<Vec<Order>>,
-) -> Result<(), AppError> {
-    for order in orders {
-        sqlx::query!(
-            "INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)",
-            order.id,
-            order.region_id,
-            order.gift_name,
-            order.quantity,
-        )
-        .execute(&pool.pool)
-        .await?;
-    }
+    State(store): State<Store>,
+    Query(params): Query<IngestParams>,
+    Batch(orders): Batch<Order>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = store.insert_orders(&orders, &params).await?;
 
-    Ok(())
+    Ok(Json(report))
 }
 
 async fn regions_route(
-    State(pool): State<Pool>,
-    Json(regions): Json<Vec<Region>>,
-) -> Result<(), AppError> {
-    println!("{:?}", pool.pool);
-    for Region { id, name } in regions {
-        sqlx::query!("insert into regions (id, name) VALUES ($1, $2)", id, name)
-            .execute(&pool.pool)
-            .await?;
+    State(store): State<Store>,
+    Query(params): Query<IngestParams>,
+    Batch(regions): Batch<Region>,
+) -> Result<impl IntoResponse, AppError> {
+    if params.reserve_stock {
+        return Err(AppError::bad_request(
+            "reserve_stock only applies to orders",
+        ));
     }
-    Ok(())
-}
-
-async fn regions_total_route(State(pool): State<Pool>) -> impl IntoResponse {
-    if let Ok(records) = sqlx::query!(
-        r#"SELECT regions.name as "region_name!", SUM(orders.quantity) as "total!" FROM orders
-  INNER JOIN regions ON orders.region_id=regions.id GROUP BY regions.name ORDER BY regions.name"#
-    )
-    .fetch_all(&pool.pool)
-    .await
-    {
-        Json(serde_json::json!(records
+    let report = store.insert_regions(&regions, params.on_conflict).await?;
+
+    Ok(Json(report))
+}
+
+// Levels of the region tree `/regions/total` can descend.
+const MAX_REGION_DEPTH: i32 = 32;
+
+#[derive(Deserialize, Debug)]
+struct TotalsParams {
+    depth: Option<i32>,
+}
+
+// Flat totals per region name, or with `depth` the region tree down to that
+// many levels, each region counting the orders of the regions below it.
+async fn regions_total_route(
+    State(store): State<Store>,
+    Query(params): Query<TotalsParams>,
+    format: Format,
+) -> Result<Response, AppError> {
+    let Some(depth) = params.depth else {
+        let totals = store.region_totals().await?;
+        return format.table(&totals).await;
+    };
+
+    if !(1..=MAX_REGION_DEPTH).contains(&depth) {
+        return Err(AppError::bad_request(format!(
+            "depth must be between 1 and {MAX_REGION_DEPTH}"
+        )));
+    }
+    let mut rollups = store.region_rollups(depth).await?;
+
+    // Delimited text cannot nest, so the tree goes out flat, level by level
+    // and siblings in the same order as in the tree.
+    if format.is_delimited() {
+        rollups.sort_by(|a, b| (a.level, &a.name, a.id).cmp(&(b.level, &b.name, b.id)));
+        return format.table(&rollups).await;
+    }
+    format.table(&RegionNode::tree(rollups)).await
+}
+
+async fn regions_toplist_route(
+    Path(number): Path<i64>,
+    State(store): State<Store>,
+    format: Format,
+) -> Result<Response, AppError> {
+    if number < 0 {
+        return Err(AppError::bad_request("number must not be negative"));
+    }
+    let top_lists = store.top_lists(number).await?;
+
+    // Delimited text cannot nest, so every gift gets a row of its own, and
+    // regions without any a row with neither rank nor gift.
+    if format.is_delimited() {
+        let rows = top_lists
             .iter()
-            .map(|r| {
-                serde_json::json!({
-                  "region": r.region_name,
-                  "total": r.total})
+            .flat_map(|top_list| {
+                let gifts = top_list
+                    .top_gifts
+                    .iter()
+                    .enumerate()
+                    .map(|(index, gift)| (Some(index + 1), Some(gift.as_str())));
+                let empty = top_list.top_gifts.is_empty().then_some((None, None));
+                gifts.chain(empty).map(|(rank, gift)| TopGiftRow {
+                    region: &top_list.region,
+                    rank,
+                    gift,
+                })
+            })
+            .collect::<Vec<_>>();
+        return format.table(&rows).await;
+    }
+    format.table(&top_lists).await
+}
+
+#[derive(Serialize, Debug)]
+struct TopGiftRow<'a> {
+    region: &'a str,
+    rank: Option<usize>,
+    gift: Option<&'a str>,
+}
+
+impl Columns for TopGiftRow<'_> {
+    const COLUMNS: &'static [&'static str] = &["region", "rank", "gift"];
+}
+
+async fn export_orders_route(
+    State(store): State<Store>,
+    format: Format,
+) -> Result<Response, AppError> {
+    let pages = store.export_orders().await?;
+
+    Ok(format.stream(pages))
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+const MAX_REGION_IDS: usize = 1000;
+
+// Query string of `/analytics`. Lists are comma separated.
+#[derive(Deserialize, Debug)]
+struct AnalyticsParams {
+    group_by: Option<String>,
+    region_ids: Option<String>,
+    gift_prefix: Option<String>,
+    min_quantity: Option<i32>,
+    max_quantity: Option<i32>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    direction: Direction,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl TryFrom<AnalyticsParams> for AnalyticsQuery {
+    type Error = AppError;
+
+    fn try_from(params: AnalyticsParams) -> Result<Self, Self::Error> {
+        let (mut by_region, mut by_gift) = (false, false);
+        for dimension in list(params.group_by.as_deref().unwrap_or("region")) {
+            match dimension {
+                "region" => by_region = true,
+                "gift" => by_gift = true,
+                _ => {
+                    return Err(AppError::bad_request(format!(
+                        "cannot group by {dimension:?}, expected region or gift"
+                    )))
+                }
+            }
+        }
+
+        let region_ids = params
+            .region_ids
+            .as_deref()
+            .map(|ids| {
+                list(ids)
+                    .map(|id| {
+                        id.parse::<i32>()
+                            .map_err(|_| AppError::bad_request(format!("invalid region id {id:?}")))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
             })
-            .collect::<Vec<_>>()))
+            .transpose()?;
+        match &region_ids {
+            Some(ids) if ids.is_empty() => {
+                return Err(AppError::bad_request(
+                    "region_ids must list at least one id",
+                ))
+            }
+            Some(ids) if ids.len() > MAX_REGION_IDS => {
+                return Err(AppError::bad_request(format!(
+                    "region_ids can list at most {MAX_REGION_IDS} ids"
+                )))
+            }
+            _ => {}
+        }
+
+        if let (Some(min), Some(max)) = (params.min_quantity, params.max_quantity) {
+            if min > max {
+                return Err(AppError::bad_request(
+                    "min_quantity must not be greater than max_quantity",
+                ));
+            }
+        }
+
+        match params.sort {
+            SortKey::Region if !by_region => {
+                return Err(AppError::bad_request(
+                    "cannot sort by region without grouping by it",
+                ))
+            }
+            SortKey::Gift if !by_gift => {
+                return Err(AppError::bad_request(
+                    "cannot sort by gift without grouping by it",
+                ))
+            }
+            _ => {}
+        }
+
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(AppError::bad_request(format!(
+                "limit must be between 1 and {MAX_PAGE_SIZE}"
+            )));
+        }
+        let offset = params.offset.unwrap_or(0);
+        if offset < 0 {
+            return Err(AppError::bad_request("offset must not be negative"));
+        }
+
+        Ok(AnalyticsQuery {
+            by_region,
+            by_gift,
+            region_ids,
+            gift_prefix: params.gift_prefix.filter(|prefix| !prefix.is_empty()),
+            min_quantity: params.min_quantity,
+            max_quantity: params.max_quantity,
+            sort: params.sort,
+            direction: params.direction,
+            limit,
+            offset,
+        })
+    }
+}
+
+// Items of a comma separated list, ignoring blanks.
+fn list(items: &str) -> impl Iterator<Item = &str> {
+    items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+async fn analytics_route(
+    State(store): State<Store>,
+    Query(params): Query<AnalyticsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let analytics = store.analytics(&params.try_into()?).await?;
+
+    Ok(Json(analytics))
+}
+
+async fn gifts_route(State(store): State<Store>) -> Result<impl IntoResponse, AppError> {
+    let gifts = store.gifts().await?;
+
+    Ok(Json(gifts))
+}
+
+async fn create_gift_route(
+    State(store): State<Store>,
+    Json(gift): Json<Gift>,
+) -> Result<impl IntoResponse, AppError> {
+    check_gift(&gift)?;
+    store.create_gift(&gift).await?;
+
+    Ok((StatusCode::CREATED, Json(gift)))
+}
+
+async fn gift_route(
+    Path(name): Path<String>,
+    State(store): State<Store>,
+) -> Result<impl IntoResponse, AppError> {
+    let gift = store
+        .gift(&name)
+        .await?
+        .ok_or_else(|| gift_not_found(&name))?;
+
+    Ok(Json(gift))
+}
+
+// Body of `PUT /gifts/:name`, the name coming from the path.
+#[derive(Deserialize, Debug)]
+struct GiftStock {
+    stock: i32,
+    #[serde(default)]
+    low_stock_threshold: i32,
+}
+
+async fn put_gift_route(
+    Path(name): Path<String>,
+    State(store): State<Store>,
+    Json(stock): Json<GiftStock>,
+) -> Result<impl IntoResponse, AppError> {
+    let gift = Gift {
+        name,
+        stock: stock.stock,
+        low_stock_threshold: stock.low_stock_threshold,
+    };
+    check_gift(&gift)?;
+    let created = store.put_gift(&gift).await?;
+
+    let status = if created {
+        StatusCode::CREATED
     } else {
-        Json(serde_json::json!({"region": null, "total": 0}))
+        StatusCode::OK
+    };
+    Ok((status, Json(gift)))
+}
+
+async fn delete_gift_route(
+    Path(name): Path<String>,
+    State(store): State<Store>,
+) -> Result<impl IntoResponse, AppError> {
+    if !store.delete_gift(&name).await? {
+        return Err(gift_not_found(&name));
     }
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
-#[derive(Serialize)]
-struct TopList {
-    region: String,
-    top_gifts: Vec<String>,
+#[derive(Deserialize, Debug)]
+struct LowStockParams {
+    threshold: Option<i32>,
 }
 
-async fn regions_toplist_route(
-    Path(number): Path<i64>,
-    State(pool): State<Pool>,
+// Gifts running low, below the threshold given or else their own.
+async fn low_stock_route(
+    State(store): State<Store>,
+    Query(params): Query<LowStockParams>,
 ) -> Result<impl IntoResponse, AppError> {
-    let top_lists = sqlx::query_as!(
-      TopList,
-      r#"select r.name as "region!", COALESCE(NULLIF(ARRAY_AGG(o.gift_name), '{NULL}'), '{}'::text[]) AS "top_gifts!" from regions r left join LATERAL  
-      (
-          select gift_name, sum(quantity) as sum_quantity from orders where r.id = region_id group by gift_name, region_id order by sum_quantity desc limit $1
-      ) o on true group by r.name order by r.name
-       ;"#, number
-  )
-  .fetch_all(&pool.pool)
-  .await?;
-
-    Ok(Json(top_lists))
+    if params.threshold.is_some_and(|threshold| threshold < 0) {
+        return Err(AppError::bad_request("threshold must not be negative"));
+    }
+    let gifts = store.low_stock(params.threshold).await?;
+
+    Ok(Json(gifts))
+}
+
+fn check_gift(gift: &Gift) -> Result<(), AppError> {
+    match gift.violation() {
+        Some(violation) => Err(AppError::bad_request(format!(
+            "gift {:?} is invalid: {violation}",
+            gift.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn gift_not_found(name: &str) -> AppError {
+    AppError::not_found(format!("gift {name:?} is not in the catalog"))
 }
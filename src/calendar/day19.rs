@@ -1,41 +1,151 @@
 use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc, RwLock,
-    },
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::pin,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
 use axum::{
+    async_trait,
     extract::{
-        ws::{Message::Text, WebSocket},
-        Path, State, WebSocketUpgrade,
+        ws::{
+            close_code, CloseFrame,
+            Message::{Close, Text},
+            WebSocket,
+        },
+        FromRequestParts, Path, Query, State, WebSocketUpgrade,
     },
+    http::{header, request::Parts},
     response::{IntoResponse, Response},
     routing::{get, post},
-    Router,
+    Json, Router,
 };
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{
+    broadcast::{self, error::RecvError, Receiver, Sender},
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
+use ulid::Ulid;
 
-use tokio::sync::broadcast::{self, Sender};
-
-use anyhow;
-
+use super::db::{ChatMessage, ChatViews, Store};
 use super::error::AppError;
 
 use futures_util::{sink::SinkExt, stream::StreamExt};
 
 use tracing::warn;
 
-pub fn task() -> Router {
-    Router::new()
+// Messages kept per room by `task`, replayed to everyone joining the room.
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+// Messages in a page of history, unless asked for otherwise, and at most.
+const HISTORY_PAGE: i64 = 50;
+const MAX_HISTORY_PAGE: i64 = 100;
+
+// Version of the envelope protocol, spoken by clients joining with `?v=1`.
+// Everyone else gets the plain frames of the original challenge.
+const PROTOCOL_VERSION: u8 = 1;
+
+const MAX_MESSAGE_LEN: usize = 128;
+const MAX_REACTION_LEN: usize = 32;
+
+// How long kicked users stay muted, so that rejoining does not give them
+// their say back.
+const KICK_MUTE: TimeDelta = TimeDelta::minutes(5);
+
+// How the chat rooms keep what is said and seen in them.
+#[derive(Debug, Clone)]
+pub struct ChatOptions {
+    // Messages kept per room, replayed to everyone joining the room. At least
+    // the message just sent is always kept.
+    pub history_size: usize,
+    // How often view counts are written to the store, so they survive a
+    // restart. They are only kept in memory without.
+    pub flush_interval: Option<Duration>,
+    // Frames each connection may send, as many as it likes without.
+    pub rate_limit: Option<RateLimit>,
+    // Offences, like going over the rate limit or talking while muted, after
+    // which a connection is closed.
+    pub max_offences: u32,
+    // Token moderators send as `Authorization: Bearer <token>` to slow down
+    // rooms and mute or kick users. Nobody can without.
+    pub admin_token: Option<String>,
+}
+
+impl Default for ChatOptions {
+    fn default() -> Self {
+        Self {
+            history_size: DEFAULT_HISTORY_SIZE,
+            flush_interval: Some(Duration::from_secs(5)),
+            // Enough for the bursts of the challenge validator, which tweets
+            // as fast as it can.
+            rate_limit: Some(RateLimit {
+                per_second: 10.0,
+                burst: 50,
+            }),
+            max_offences: 3,
+            admin_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    // Frames a second, on average.
+    pub per_second: f64,
+    // Frames that may be sent at once after a quiet spell.
+    pub burst: u32,
+}
+
+// View counts only reach the store through the `ViewsFlusher` handed out by
+// `task_with_options`, so they stay in memory here.
+pub fn task(store: Store) -> Router {
+    let options = ChatOptions {
+        flush_interval: None,
+        ..ChatOptions::default()
+    };
+    task_with_options(store, options).0
+}
+
+// Same as `task`, along with what writes the view counts to the store when
+// `options` has a flush interval, to be run next to the router.
+pub fn task_with_options(store: Store, options: ChatOptions) -> (Router, Option<ViewsFlusher>) {
+    let views = Views {
+        persisted: options.flush_interval.is_some(),
+        ..Views::default()
+    };
+    let flusher = options.flush_interval.map(|interval| ViewsFlusher {
+        views: views.clone(),
+        store: store.clone(),
+        interval,
+    });
+
+    let router = Router::new()
         .route("/ws/ping", get(ping_route))
         .route("/reset", post(reset_route))
         .route("/views", get(views_route))
         .route("/ws/room/:room/user/:user", get(tweet_route))
+        .route("/rooms", get(rooms_route))
+        .route("/rooms/:room/users", get(room_users_route))
+        .route("/rooms/:room/history", get(history_route))
+        .route("/rooms/:room/slow_mode", post(slow_mode_route))
+        .route("/rooms/:room/users/:user/mute", post(mute_route))
+        .route("/rooms/:room/users/:user/unmute", post(unmute_route))
+        .route("/rooms/:room/users/:user/kick", post(kick_route))
         .with_state(TwitterState {
-            views: Arc::new(AtomicU64::new(0)),
+            views,
             rooms: Arc::new(RwLock::new(HashMap::new())),
-        })
+            rules: Default::default(),
+            store,
+            history_size: i64::try_from(options.history_size.max(1)).unwrap_or(i64::MAX),
+            rate_limit: options.rate_limit,
+            max_offences: options.max_offences,
+            admin_token: options.admin_token.map(Arc::from),
+        });
+    (router, flusher)
 }
 
 async fn ping_route(ws: WebSocketUpgrade) -> impl IntoResponse {
@@ -62,122 +172,1170 @@ struct TweetInput {
     message: String,
 }
 
-impl TryFrom<&String> for TweetInput {
+impl TryFrom<&str> for TweetInput {
     type Error = AppError;
 
-    fn try_from(value: &String) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
         let tweet_input = serde_json::from_str::<Self>(value)
-            .map_err(|e| anyhow::anyhow!("Error parsing TweetInput: {}", e))?;
+            .map_err(|e| AppError::bad_request(format!("Error parsing TweetInput: {}", e)))?;
+        check_message(&tweet_input.message)?;
+
+        Ok(tweet_input)
+    }
+}
+
+fn check_message(message: &str) -> Result<(), AppError> {
+    if message.len() > MAX_MESSAGE_LEN {
+        return Err(AppError::payload_too_large(format!(
+            "Message length cannot be over {MAX_MESSAGE_LEN}"
+        )));
+    }
+    Ok(())
+}
+
+// Text sent to the users of a room for `message`.
+fn tweet_text(message: &ChatMessage) -> String {
+    json!({ "user": message.user, "message": message.message }).to_string()
+}
+
+// Id of `message` on the wire: a ULID of the time it was sent, with its id
+// in the history as the random part.
+fn message_ulid(message: &ChatMessage) -> Ulid {
+    Ulid::from_parts(
+        u64::try_from(message.sent_at.timestamp_millis()).unwrap_or(0),
+        u128::try_from(message.id).unwrap_or(0),
+    )
+}
+
+// Way a client talks to its room, chosen when joining.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    // `{"message"}` in, `{"user", "message"}` out, errors only logged.
+    Plain,
+    // `Envelope`s out, `ClientFrame`s in, errors sent back.
+    Envelope,
+}
 
-        if tweet_input.message.len() > 128 {
-            return Err(anyhow::anyhow!("Message length cannot be over 128").into());
+// Frame sent by a client speaking the envelope protocol.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientFrame {
+    Tweet { message: String },
+    Edit { id: String, message: String },
+    Delete { id: String },
+    Reaction { id: String, reaction: String },
+    Typing { typing: bool },
+}
+
+impl ClientFrame {
+    fn parse(text: &str, protocol: Protocol) -> Result<Self, AppError> {
+        match protocol {
+            Protocol::Envelope => serde_json::from_str(text)
+                .map_err(|err| AppError::bad_request(format!("invalid frame: {err}"))),
+            Protocol::Plain => {
+                if let Ok(TypingInput { typing }) = serde_json::from_str(text) {
+                    return Ok(Self::Typing { typing });
+                }
+                let TweetInput { message } = TweetInput::try_from(text)?;
+                Ok(Self::Tweet { message })
+            }
         }
+    }
+}
 
-        Ok(tweet_input)
+// Frame of the envelope protocol, for anything happening in a room.
+#[derive(Debug, Clone, Serialize)]
+struct Envelope {
+    v: u8,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    // ULID of the tweet the frame is about.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    room: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reaction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typing: Option<bool>,
+    // HTTP status and detail of an error frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    ts: DateTime<Utc>,
+}
+
+impl Envelope {
+    fn new(kind: &'static str, room: i32, ts: DateTime<Utc>) -> Self {
+        Self {
+            v: PROTOCOL_VERSION,
+            kind,
+            id: None,
+            room,
+            user: None,
+            message: None,
+            reaction: None,
+            typing: None,
+            status: None,
+            error: None,
+            ts,
+        }
+    }
+
+    // Frame of `kind` about `message`, by its author unless said otherwise.
+    fn about(kind: &'static str, message: &ChatMessage, ts: DateTime<Utc>) -> Self {
+        Self {
+            id: Some(message_ulid(message).to_string()),
+            user: Some(message.user.clone()),
+            ..Self::new(kind, message.room, ts)
+        }
+    }
+
+    fn tweet(message: &ChatMessage) -> Self {
+        Self {
+            message: Some(message.message.clone()),
+            ..Self::about("tweet", message, message.sent_at)
+        }
+    }
+
+    fn presence(room: i32, event: &PresenceEvent, ts: DateTime<Utc>) -> Self {
+        let (kind, typing) = match event {
+            PresenceEvent::Join { .. } => ("join", None),
+            PresenceEvent::Leave { .. } => ("leave", None),
+            PresenceEvent::Typing { typing, .. } => ("typing", Some(*typing)),
+        };
+        Self {
+            user: Some(event.user().to_string()),
+            typing,
+            ..Self::new(kind, room, ts)
+        }
+    }
+
+    fn error(room: i32, err: &AppError) -> Self {
+        Self {
+            status: Some(err.status().as_u16()),
+            error: Some(err.detail()),
+            ..Self::new("error", room, Utc::now())
+        }
+    }
+
+    fn text(&self) -> String {
+        serde_json::to_string(self).unwrap()
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-struct Tweet {
-    user: String,
-    message: TweetInput,
+// Text sent to a client speaking `protocol` for `message`.
+fn tweet_frame(message: &ChatMessage, protocol: Protocol) -> String {
+    match protocol {
+        Protocol::Plain => tweet_text(message),
+        Protocol::Envelope => Envelope::tweet(message).text(),
+    }
 }
 
-impl From<Tweet> for String {
-    fn from(value: Tweet) -> Self {
-        format!(
-            r#"{{
-      "user": "{}",
-      "message": "{}"
-    }}"#,
-            value.user, value.message.message
-        )
+// Asks for the typing indicator of the user to be shown, or hidden.
+#[derive(Debug, Deserialize)]
+struct TypingInput {
+    typing: bool,
+}
+
+// Change in who is in a room, sent to the users of the room that asked for
+// events.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum PresenceEvent {
+    Join { user: String },
+    Leave { user: String },
+    Typing { user: String, typing: bool },
+}
+
+impl PresenceEvent {
+    fn user(&self) -> &str {
+        match self {
+            Self::Join { user } | Self::Leave { user } | Self::Typing { user, .. } => user,
+        }
     }
 }
 
+#[derive(Debug, Clone)]
+enum RoomEvent {
+    Tweet(ChatMessage),
+    // Edit, deletion of or reaction to a tweet, only sent to clients
+    // speaking the envelope protocol.
+    Update(Envelope),
+    Presence(PresenceEvent, DateTime<Utc>),
+}
+
 #[derive(Debug)]
 struct RoomState {
-    sender: Sender<Tweet>,
+    sender: Sender<RoomEvent>,
+    // Held while a message is stored and broadcast, so that messages reach
+    // the channel in the order of their ids.
+    posting: Mutex<()>,
+    // Connections of each user in the room, by the channel of their
+    // notices. Only changed with the rooms locked for writing, so an empty
+    // room can be dropped safely.
+    occupants: std::sync::Mutex<BTreeMap<String, Vec<mpsc::Sender<Notice>>>>,
+}
+
+// Notices waiting to be sent to a connection. The last place is kept for
+// closing it, so errors are dropped rather than pile up for a client not
+// reading them.
+const MAX_NOTICES: usize = 16;
+
+// Said to a single connection by the server rather than the room.
+#[derive(Debug)]
+enum Notice {
+    Error(AppError),
+    // Closes the connection for the reason given.
+    Close(AppError),
+}
+
+// What the users of a room may do, kept whether anyone is in the room or not
+// until the chat is reset.
+#[derive(Debug, Default)]
+struct RoomRules {
+    // Least time between two tweets of a user.
+    slow_mode: Option<Duration>,
+    // Muted users, until when or for good.
+    muted: HashMap<String, Option<DateTime<Utc>>>,
+    // When each user last tweeted, for slow mode.
+    last_tweets: HashMap<String, Instant>,
+}
+
+// The slow mode slot a tweet took, given back when it does not go out.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    taken_at: Instant,
+    // When the user tweeted before, if they did.
+    previous: Option<Instant>,
+}
+
+impl RoomRules {
+    // Checks that `user` may send `frame` now. A tweet in slow mode takes the
+    // slot of the user right away, so that no other connection of theirs
+    // gets in before it went out.
+    fn check(
+        &mut self,
+        room: i32,
+        user: &str,
+        frame: &ClientFrame,
+    ) -> Result<Option<Slot>, AppError> {
+        match self.muted.get(user) {
+            Some(None) => return Err(AppError::forbidden(format!("muted in room {room}"))),
+            Some(Some(until)) if *until > Utc::now() => {
+                return Err(AppError::forbidden(format!(
+                    "muted in room {room} until {}",
+                    until.to_rfc3339_opts(SecondsFormat::Secs, true)
+                )));
+            }
+            Some(Some(_)) => {
+                self.muted.remove(user);
+            }
+            None => {}
+        }
+
+        let (ClientFrame::Tweet { .. }, Some(slow_mode)) = (frame, self.slow_mode) else {
+            return Ok(None);
+        };
+        let now = Instant::now();
+        if let Some(last) = self.last_tweets.get(user) {
+            let wait = slow_mode.saturating_sub(now - *last);
+            if !wait.is_zero() {
+                return Err(AppError::too_many_requests(format!(
+                    "room {room} is in slow mode, wait {} ms",
+                    wait.as_millis()
+                )));
+            }
+        }
+        let previous = self.last_tweets.insert(user.to_string(), now);
+        Ok(Some(Slot {
+            taken_at: now,
+            previous,
+        }))
+    }
+
+    // Gives back the slot of `user`, whose tweet did not go out, unless slow
+    // mode was changed meanwhile.
+    fn give_back(&mut self, user: &str, slot: Slot) {
+        if self.last_tweets.get(user) != Some(&slot.taken_at) {
+            return;
+        }
+        match slot.previous {
+            Some(previous) => self.last_tweets.insert(user.to_string(), previous),
+            None => self.last_tweets.remove(user),
+        };
+    }
+}
+
+// Frames a connection may still send, refilled at the rate limit.
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
 }
 
-#[derive(Clone, Debug)]
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    // Takes a token for a frame, false if there is none left.
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = (now - self.refilled_at).as_secs_f64() * self.limit.per_second;
+        self.tokens = (self.tokens + refill).min(f64::from(self.limit.burst));
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// Why a frame was turned down.
+#[derive(Debug)]
+enum Refused {
+    // Broke the rate limit or a rule of the room, for which a connection is
+    // closed when repeated.
+    Offence(AppError),
+    // Anything else, like a malformed frame or an edit of someone else's
+    // tweet.
+    Error(AppError),
+}
+
+impl From<AppError> for Refused {
+    fn from(err: AppError) -> Self {
+        Self::Error(err)
+    }
+}
+
+#[derive(Clone)]
 struct TwitterState {
-    views: Arc<AtomicU64>,
-    rooms: Arc<RwLock<HashMap<i32, RoomState>>>,
+    views: Views,
+    rooms: Arc<RwLock<HashMap<i32, Arc<RoomState>>>>,
+    rules: Arc<std::sync::Mutex<HashMap<i32, RoomRules>>>,
+    store: Store,
+    history_size: i64,
+    rate_limit: Option<RateLimit>,
+    max_offences: u32,
+    admin_token: Option<Arc<str>>,
 }
 
 impl RoomState {
     fn new() -> Self {
         Self {
             sender: broadcast::channel(100).0,
+            posting: Mutex::new(()),
+            occupants: Default::default(),
         }
     }
+
+    fn announce(&self, event: PresenceEvent) {
+        // Nobody being in the room to hear it is fine.
+        let _ = self.sender.send(RoomEvent::Presence(event, Utc::now()));
+    }
 }
 
-async fn reset_route(State(state): State<TwitterState>) {
-    state.views.store(0, Ordering::Relaxed);
+impl TwitterState {
+    fn room(&self, room: i32) -> Option<Arc<RoomState>> {
+        self.rooms.read().unwrap().get(&room).cloned()
+    }
+
+    // Adds a connection of `user` to `room`, announcing the user unless
+    // already there. The connection hears about everything from here on,
+    // and gets its own `notices`.
+    fn join(
+        &self,
+        room: i32,
+        user: &str,
+        notices: mpsc::Sender<Notice>,
+    ) -> (Arc<RoomState>, Receiver<RoomEvent>) {
+        let mut rooms = self.rooms.write().unwrap();
+        let room_state = rooms
+            .entry(room)
+            .or_insert_with(|| Arc::new(RoomState::new()))
+            .clone();
+        let receiver = room_state.sender.subscribe();
+
+        let mut occupants = room_state.occupants.lock().unwrap();
+        let connections = occupants.entry(user.to_string()).or_default();
+        connections.push(notices);
+        if connections.len() == 1 {
+            room_state.announce(PresenceEvent::Join {
+                user: user.to_string(),
+            });
+        }
+        drop(occupants);
+
+        (room_state, receiver)
+    }
+
+    // Removes a connection of `user` from `room`, announcing the user gone
+    // with the last one. A room left empty is dropped.
+    fn leave(&self, room: i32, user: &str, notices: &mpsc::Sender<Notice>) {
+        let mut rooms = self.rooms.write().unwrap();
+        let Some(room_state) = rooms.get(&room) else {
+            return;
+        };
+
+        let mut occupants = room_state.occupants.lock().unwrap();
+        if let Some(connections) = occupants.get_mut(user) {
+            connections.retain(|connection| !connection.same_channel(notices));
+            if connections.is_empty() {
+                occupants.remove(user);
+                room_state.announce(PresenceEvent::Leave {
+                    user: user.to_string(),
+                });
+            }
+        }
+        let is_empty = occupants.is_empty();
+        drop(occupants);
+
+        if is_empty {
+            rooms.remove(&room);
+        }
+    }
+
+    // Changes the rules of `room`, whether anyone is in it or not.
+    fn change_rules(&self, room: i32, change: impl FnOnce(&mut RoomRules)) {
+        change(self.rules.lock().unwrap().entry(room).or_default());
+    }
+
+    // Stores the message of `user` in the history of `room` and passes it on
+    // to everyone in the room.
+    async fn post(
+        &self,
+        room: i32,
+        room_state: &RoomState,
+        user: &str,
+        message: &str,
+    ) -> Result<(), AppError> {
+        let _posting = room_state.posting.lock().await;
+        let message = self
+            .store
+            .append_chat(room, user, message, Utc::now(), self.history_size)
+            .await?;
+        let _ = room_state.sender.send(RoomEvent::Tweet(message));
+        Ok(())
+    }
+
+    // Acts on a frame sent by `user` to `room`.
+    async fn handle(
+        &self,
+        room: i32,
+        room_state: &RoomState,
+        user: &str,
+        frame: ClientFrame,
+    ) -> Result<(), Refused> {
+        let slot = match self.rules.lock().unwrap().get_mut(&room) {
+            Some(rules) => rules.check(room, user, &frame).map_err(Refused::Offence)?,
+            None => None,
+        };
+
+        let update = match frame {
+            ClientFrame::Tweet { message } => {
+                let posted = match check_message(&message) {
+                    Ok(()) => self.post(room, room_state, user, &message).await,
+                    Err(err) => Err(err),
+                };
+                if let (Err(_), Some(slot)) = (&posted, slot) {
+                    if let Some(rules) = self.rules.lock().unwrap().get_mut(&room) {
+                        rules.give_back(user, slot);
+                    }
+                }
+                return Ok(posted?);
+            }
+            ClientFrame::Typing { typing } => {
+                room_state.announce(PresenceEvent::Typing {
+                    user: user.to_string(),
+                    typing,
+                });
+                return Ok(());
+            }
+            ClientFrame::Edit { id, message } => {
+                check_message(&message)?;
+                let mut tweet = self.own_tweet(room, &id, user).await?;
+                self.store.edit_chat(room, tweet.id, &message).await?;
+                tweet.message = message;
+                Envelope {
+                    message: Some(tweet.message.clone()),
+                    ..Envelope::about("edit", &tweet, Utc::now())
+                }
+            }
+            ClientFrame::Delete { id } => {
+                let tweet = self.own_tweet(room, &id, user).await?;
+                self.store.delete_chat(room, tweet.id).await?;
+                Envelope::about("delete", &tweet, Utc::now())
+            }
+            ClientFrame::Reaction { id, reaction } => {
+                if reaction.is_empty() || reaction.len() > MAX_REACTION_LEN {
+                    return Err(AppError::bad_request(format!(
+                        "reaction must be 1 to {MAX_REACTION_LEN} bytes long"
+                    ))
+                    .into());
+                }
+                let tweet = self.tweet(room, &id).await?;
+                Envelope {
+                    user: Some(user.to_string()),
+                    reaction: Some(reaction),
+                    ..Envelope::about("reaction", &tweet, Utc::now())
+                }
+            }
+        };
+
+        let _ = room_state.sender.send(RoomEvent::Update(update));
+        Ok(())
+    }
+
+    // Tweet of `room` with ULID `id`, while still in the history.
+    async fn tweet(&self, room: i32, id: &str) -> Result<ChatMessage, AppError> {
+        let not_found = || AppError::not_found(format!("no tweet {id} in room {room}"));
+        let ulid = Ulid::from_string(id).map_err(|_| not_found())?;
+        let message_id = i64::try_from(ulid.random()).map_err(|_| not_found())?;
+
+        match self.store.chat_message(room, message_id).await? {
+            Some(message) if message_ulid(&message) == ulid => Ok(message),
+            _ => Err(not_found()),
+        }
+    }
+
+    // Same as `tweet`, for changes only its author may make.
+    async fn own_tweet(&self, room: i32, id: &str, user: &str) -> Result<ChatMessage, AppError> {
+        let message = self.tweet(room, id).await?;
+        if message.user != user {
+            return Err(AppError::forbidden(format!(
+                "tweet {id} can only be changed by {}",
+                message.user
+            )));
+        }
+        Ok(message)
+    }
+}
+
+// Tweets seen per room and user, along with those not yet flushed to the
+// store.
+#[derive(Debug, Default)]
+struct ViewCounts {
+    totals: HashMap<(i32, String), u64>,
+    unflushed: HashMap<(i32, String), u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Views {
+    counts: Arc<std::sync::Mutex<ViewCounts>>,
+    // Held while counts are read from or written to the store.
+    flushing: Arc<Mutex<()>>,
+    // Whether the counts are kept in the store.
+    persisted: bool,
+}
+
+fn counts_for(key: &(i32, String), room: Option<i32>, user: Option<&str>) -> bool {
+    room.is_none_or(|room| room == key.0) && user.is_none_or(|user| user == key.1)
+}
+
+impl Views {
+    // Counts a tweet of `room` seen by `user`.
+    fn add(&self, room: i32, user: &str) {
+        let key = (room, user.to_string());
+        let mut counts = self.counts.lock().unwrap();
+        *counts.unflushed.entry(key.clone()).or_default() += 1;
+        *counts.totals.entry(key).or_default() += 1;
+    }
+
+    // Tweets seen in `room` by `user`, in every room or by anyone when not
+    // given.
+    fn count(&self, room: Option<i32>, user: Option<&str>) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .totals
+            .iter()
+            .filter(|(key, _)| counts_for(key, room, user))
+            .map(|(_, views)| views)
+            .sum()
+    }
+
+    async fn reset(
+        &self,
+        store: &Store,
+        room: Option<i32>,
+        user: Option<&str>,
+    ) -> Result<(), AppError> {
+        let _flushing = self.flushing.lock().await;
+        if self.persisted {
+            store.reset_chat_views(room, user).await?;
+        }
+
+        let counts = &mut *self.counts.lock().unwrap();
+        counts.totals.retain(|key, _| !counts_for(key, room, user));
+        counts
+            .unflushed
+            .retain(|key, _| !counts_for(key, room, user));
+        Ok(())
+    }
+
+    async fn flush(&self, store: &Store) {
+        let _flushing = self.flushing.lock().await;
+        let unflushed = std::mem::take(&mut self.counts.lock().unwrap().unflushed);
+        if unflushed.is_empty() {
+            return;
+        }
+
+        let views = unflushed
+            .iter()
+            .map(|((room, user), &views)| ChatViews {
+                room: *room,
+                user: user.clone(),
+                views: i64::try_from(views).unwrap_or(i64::MAX),
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) = store.add_chat_views(&views).await {
+            // Tried again with the next flush.
+            warn!("cannot flush the view counts: {err:?}");
+            let counts = &mut *self.counts.lock().unwrap();
+            for (key, views) in unflushed {
+                *counts.unflushed.entry(key).or_default() += views;
+            }
+        }
+    }
 }
 
-async fn views_route(State(state): State<TwitterState>) -> String {
-    state.views.load(Ordering::Relaxed).to_string()
+// Writes the view counts of a router to the store.
+pub struct ViewsFlusher {
+    views: Views,
+    store: Store,
+    interval: Duration,
+}
+
+impl ViewsFlusher {
+    // Adds the counts kept in the store, then writes what was seen since to
+    // the store every `interval`, and once more when `shutdown` resolves.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        let Self {
+            views,
+            store,
+            interval,
+        } = self;
+        match store.chat_views().await {
+            Ok(stored) => {
+                let counts = &mut *views.counts.lock().unwrap();
+                for ChatViews { room, user, views } in stored {
+                    *counts.totals.entry((room, user)).or_default() +=
+                        u64::try_from(views).unwrap_or(0);
+                }
+            }
+            Err(err) => warn!("cannot load the view counts: {err:?}"),
+        }
+
+        let mut ticks = tokio::time::interval(interval);
+        let mut shutdown = pin!(shutdown);
+        loop {
+            let stopping = tokio::select! {
+                _ = ticks.tick() => false,
+                _ = &mut shutdown => true,
+            };
+            views.flush(&store).await;
+            if stopping {
+                return;
+            }
+        }
+    }
+}
+
+// Connection of a user to a room, leaving the room when dropped.
+struct Presence {
+    state: TwitterState,
+    room: i32,
+    user: String,
+    notices: mpsc::Sender<Notice>,
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        self.state.leave(self.room, &self.user, &self.notices);
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ViewsParams {
+    room: Option<i32>,
+    // Who saw the tweets.
+    user: Option<String>,
+}
+
+// Resets the view counts of a room or user, or everything including the
+// history of every room when neither is given.
+async fn reset_route(
+    Query(params): Query<ViewsParams>,
+    State(state): State<TwitterState>,
+) -> Result<(), AppError> {
+    state
+        .views
+        .reset(&state.store, params.room, params.user.as_deref())
+        .await?;
+    if params.room.is_none() && params.user.is_none() {
+        state.store.reset_chat().await?;
+        state.rules.lock().unwrap().clear();
+    }
+    Ok(())
+}
+
+async fn views_route(
+    Query(params): Query<ViewsParams>,
+    State(state): State<TwitterState>,
+) -> String {
+    state
+        .views
+        .count(params.room, params.user.as_deref())
+        .to_string()
+}
+
+#[derive(Deserialize, Debug)]
+struct JoinParams {
+    // Whether to hear who joins, leaves and types besides the tweets.
+    #[serde(default)]
+    events: bool,
+    // Version of the envelope protocol spoken, plain frames without.
+    v: Option<u8>,
 }
 
 async fn tweet_route(
     ws: WebSocketUpgrade,
     Path((room, user)): Path<(i32, String)>,
+    Query(params): Query<JoinParams>,
     State(state): State<TwitterState>,
-) -> Response {
-    ws.on_upgrade(move |c| handle_tweet(c, room, user, Arc::new(state)))
+) -> Result<Response, AppError> {
+    let protocol = match params.v {
+        None => Protocol::Plain,
+        Some(PROTOCOL_VERSION) => Protocol::Envelope,
+        Some(v) => {
+            return Err(AppError::bad_request(format!(
+                "protocol version {v} is not supported, expected {PROTOCOL_VERSION}"
+            )))
+        }
+    };
+
+    Ok(ws.on_upgrade(move |c| handle_tweet(c, room, user, params.events, protocol, state)))
 }
 
-async fn handle_tweet(socket: WebSocket, room: i32, user: String, state: Arc<TwitterState>) {
-    // socket.
+async fn handle_tweet(
+    socket: WebSocket,
+    room: i32,
+    user: String,
+    events: bool,
+    protocol: Protocol,
+    state: TwitterState,
+) {
     let (mut sender, mut receiver) = socket.split();
-    #[allow(unused_assignments)]
-    let mut room_sender = None::<Sender<Tweet>>;
-
-    if let Some(room_state) = state.rooms.read().unwrap().get(&room) {
-        room_sender = Some(room_state.sender.clone());
-    }
+    let (notices, mut notice_receiver) = mpsc::channel(MAX_NOTICES);
+    // Joined before the history is read so that nothing said meanwhile is
+    // missed. Messages both replayed and received are only sent once.
+    let (room_state, mut room_receiver) = state.join(room, &user, notices.clone());
+    let presence = Presence {
+        state: state.clone(),
+        room,
+        user: user.clone(),
+        notices: notices.clone(),
+    };
 
-    if room_sender.is_none() {
-        let mut rooms = state.rooms.write().unwrap();
-        let room_state = rooms.entry(room).or_insert_with(RoomState::new);
-        room_sender = Some(room_state.sender.clone());
+    // Replayed messages are not counted as views.
+    let history = match state
+        .store
+        .chat_history(room, None, state.history_size)
+        .await
+    {
+        Ok(history) => history,
+        Err(err) => {
+            warn!("cannot replay the history of room {room}: {err:?}");
+            Vec::new()
+        }
+    };
+    let mut last_id = 0;
+    for message in history {
+        last_id = message.id;
+        if sender
+            .send(Text(tweet_frame(&message, protocol)))
+            .await
+            .is_err()
+        {
+            return;
+        }
     }
 
-    let room_sender = room_sender.unwrap();
-    let mut room_receiver = room_sender.subscribe();
-
+    let posting = state.clone();
+    // Keeps the room open until the receiving end is done, even if the
+    // client is sent away as the last one in it.
+    let room_open = room_state.clone();
+    let mut bucket = state.rate_limit.map(TokenBucket::new);
+    // Returns whether the client is being sent away, rather than gone.
     let mut send = tokio::spawn(async move {
-        while let Some(msg) = receiver.next().await {
-            let Ok(msg) = msg else {
-                return;
+        let mut offences = 0;
+        while let Some(Ok(msg)) = receiver.next().await {
+            let Text(text) = &msg else {
+                continue;
             };
-
-            if let Text(text) = &msg {
-                match TweetInput::try_from(text) {
-                    Ok(message) => {
-                        // info!("Parsed {:?}", message);
-                        let user = user.clone();
-                        let _ = room_sender.send(Tweet { user, message }).unwrap();
+            let handled = if bucket.as_mut().is_some_and(|bucket| !bucket.take()) {
+                Err(Refused::Offence(AppError::too_many_requests(
+                    "too many frames, slow down",
+                )))
+            } else {
+                match ClientFrame::parse(text, protocol) {
+                    Ok(frame) => {
+                        posting
+                            .handle(room, &room_state, &presence.user, frame)
+                            .await
                     }
-                    Err(_) => warn!("Failed to parse TweetInput"),
+                    Err(err) => Err(err.into()),
+                }
+            };
+            let (err, offended) = match handled {
+                Ok(()) => continue,
+                Err(Refused::Offence(err)) => (err, true),
+                Err(Refused::Error(err)) => (err, false),
+            };
+
+            if err.status().is_server_error() || protocol == Protocol::Plain {
+                warn!("cannot handle a frame for room {room}: {err:?}");
+            }
+            if protocol == Protocol::Envelope
+                && notices.capacity() > 1
+                && matches!(
+                    notices.try_send(Notice::Error(err)),
+                    Err(TrySendError::Closed(_))
+                )
+            {
+                return false;
+            }
+            if offended {
+                offences += 1;
+                if offences >= posting.max_offences {
+                    let reason = AppError::forbidden(format!("closed after {offences} offences"));
+                    // Only another close can have taken the last place.
+                    return !matches!(
+                        notices.try_send(Notice::Close(reason)),
+                        Err(TrySendError::Closed(_))
+                    );
                 }
             }
         }
+        false
     });
 
     let mut receive = tokio::spawn(async move {
-        while let Ok(msg) = room_receiver.recv().await {
-            let msg_str: String = msg.into();
-            let _ = state.views.fetch_add(1, Ordering::Relaxed);
-            sender.send(Text(msg_str)).await.unwrap();
+        let _room_open = room_open;
+        loop {
+            // Notices first, so that a client is sent away at once however
+            // busy the room.
+            let received = tokio::select! {
+                biased;
+                Some(notice) = notice_receiver.recv() => {
+                    let (err, closing) = match notice {
+                        Notice::Error(err) => (err, false),
+                        Notice::Close(err) => (err, true),
+                    };
+                    if protocol == Protocol::Envelope {
+                        let text = Envelope::error(room, &err).text();
+                        if sender.send(Text(text)).await.is_err() {
+                            return;
+                        }
+                    }
+                    if closing {
+                        let frame = CloseFrame {
+                            code: close_code::POLICY,
+                            reason: err.detail().into(),
+                        };
+                        let _ = sender.send(Close(Some(frame))).await;
+                        return;
+                    }
+                    continue;
+                }
+                received = room_receiver.recv() => received,
+            };
+            let messages = match received {
+                Ok(RoomEvent::Tweet(message)) => vec![message],
+                Ok(RoomEvent::Update(update)) => {
+                    if protocol == Protocol::Envelope
+                        && sender.send(Text(update.text())).await.is_err()
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                // Events about the user are of no news to them.
+                Ok(RoomEvent::Presence(event, ts)) => {
+                    if !events || event.user() == user {
+                        continue;
+                    }
+                    let text = match protocol {
+                        Protocol::Plain => serde_json::to_string(&event).unwrap(),
+                        Protocol::Envelope => Envelope::presence(room, &event, ts).text(),
+                    };
+                    if sender.send(Text(text)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                // Fell behind the channel, so whatever was missed is read
+                // back from the history. Events missed are lost.
+                Err(RecvError::Lagged(_)) => {
+                    match state
+                        .store
+                        .chat_after(room, last_id, state.history_size)
+                        .await
+                    {
+                        Ok(messages) => messages,
+                        Err(err) => {
+                            warn!("cannot catch up with room {room}: {err:?}");
+                            return;
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            for message in messages {
+                if message.id <= last_id {
+                    continue;
+                }
+                last_id = message.id;
+                state.views.add(room, &user);
+                if sender
+                    .send(Text(tweet_frame(&message, protocol)))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
         }
     });
 
     tokio::select! {
-        _ = (&mut send) => receive.abort(),
+        closing = (&mut send) => {
+            // Lets the client hear why it is being sent away.
+            if matches!(closing, Ok(true)) {
+                let _ = receive.await;
+            } else {
+                receive.abort();
+            }
+        }
         _ = (&mut receive) => send.abort(),
     };
 }
+
+#[derive(Serialize, Debug)]
+struct RoomView {
+    room: i32,
+    // Users in the room, however many times each is connected.
+    users: usize,
+}
+
+// Every room someone is in, by number.
+async fn rooms_route(State(state): State<TwitterState>) -> Json<Vec<RoomView>> {
+    let mut rooms = state
+        .rooms
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(&room, room_state)| RoomView {
+            room,
+            users: room_state.occupants.lock().unwrap().len(),
+        })
+        .collect::<Vec<_>>();
+    rooms.sort_by_key(|room| room.room);
+
+    Json(rooms)
+}
+
+// Users in `room` by name, none for a room nobody is in.
+async fn room_users_route(
+    Path(room): Path<i32>,
+    State(state): State<TwitterState>,
+) -> Json<Vec<String>> {
+    let users = state.room(room).map_or_else(Vec::new, |room_state| {
+        room_state
+            .occupants
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    });
+
+    Json(users)
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryParams {
+    // Cursor of the page, as handed out in `next`. The newest messages
+    // without.
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct HistoryPage {
+    // Tweets in the envelope format, oldest first.
+    messages: Vec<Envelope>,
+    // Cursor of the page of older messages, none on the oldest page.
+    next: Option<i64>,
+}
+
+async fn history_route(
+    Path(room): Path<i32>,
+    Query(params): Query<HistoryParams>,
+    State(state): State<TwitterState>,
+) -> Result<Json<HistoryPage>, AppError> {
+    let limit = params.limit.unwrap_or(HISTORY_PAGE);
+    if !(1..=MAX_HISTORY_PAGE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {MAX_HISTORY_PAGE}"
+        )));
+    }
+
+    // One more than asked for tells whether there is an older page.
+    let mut messages = state
+        .store
+        .chat_history(room, params.before, limit + 1)
+        .await?;
+    let next = if messages.len() as i64 > limit {
+        messages.remove(0);
+        messages.first().map(|message| message.id)
+    } else {
+        None
+    };
+
+    Ok(Json(HistoryPage {
+        messages: messages.iter().map(Envelope::tweet).collect(),
+        next,
+    }))
+}
+
+// Proof that a request comes from a moderator, who knows the admin token.
+struct Moderator;
+
+#[async_trait]
+impl FromRequestParts<TwitterState> for Moderator {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &TwitterState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = &state.admin_token else {
+            return Err(AppError::forbidden("moderation is turned off"));
+        };
+
+        let given = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if given.is_some_and(|given| tokens_match(given, token)) {
+            Ok(Self)
+        } else {
+            Err(AppError::forbidden("moderation needs the admin token"))
+        }
+    }
+}
+
+// Compares in the same time wherever the tokens differ.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Deserialize, Debug)]
+struct SlowModeParams {
+    // Least time between two tweets of a user, 0 to turn slow mode off.
+    seconds: u64,
+}
+
+async fn slow_mode_route(
+    _: Moderator,
+    Path(room): Path<i32>,
+    Query(params): Query<SlowModeParams>,
+    State(state): State<TwitterState>,
+) {
+    state.change_rules(room, |rules| {
+        rules.slow_mode = Some(Duration::from_secs(params.seconds)).filter(|slow| !slow.is_zero());
+        rules.last_tweets.clear();
+    });
+}
+
+#[derive(Deserialize, Debug)]
+struct MuteParams {
+    // How long to mute the user for, for good without.
+    seconds: Option<i64>,
+}
+
+async fn mute_route(
+    _: Moderator,
+    Path((room, user)): Path<(i32, String)>,
+    Query(params): Query<MuteParams>,
+    State(state): State<TwitterState>,
+) -> Result<(), AppError> {
+    let until = match params.seconds {
+        None => None,
+        Some(seconds @ 1..) => TimeDelta::try_seconds(seconds)
+            .and_then(|muted| Utc::now().checked_add_signed(muted))
+            .map(Some)
+            .ok_or_else(|| AppError::bad_request("seconds is out of range"))?,
+        Some(_) => return Err(AppError::bad_request("seconds must be positive")),
+    };
+
+    state.change_rules(room, |rules| {
+        rules.muted.insert(user, until);
+    });
+    Ok(())
+}
+
+async fn unmute_route(
+    _: Moderator,
+    Path((room, user)): Path<(i32, String)>,
+    State(state): State<TwitterState>,
+) {
+    if let Some(rules) = state.rules.lock().unwrap().get_mut(&room) {
+        rules.muted.remove(&user);
+    }
+}
+
+// Closes every connection of `user` to `room` and mutes them for a while,
+// unless they are muted for longer already.
+async fn kick_route(
+    _: Moderator,
+    Path((room, user)): Path<(i32, String)>,
+    State(state): State<TwitterState>,
+) -> Result<(), AppError> {
+    let not_found = || AppError::not_found(format!("{user} is not in room {room}"));
+    let room_state = state.room(room).ok_or_else(not_found)?;
+    let occupants = room_state.occupants.lock().unwrap();
+    let connections = occupants.get(&user).ok_or_else(not_found)?;
+    let until = Utc::now() + KICK_MUTE;
+    state.change_rules(room, |rules| {
+        if let Some(muted) = rules.muted.entry(user.clone()).or_insert(Some(until)) {
+            *muted = until.max(*muted);
+        }
+    });
+    for connection in connections {
+        let reason = AppError::forbidden(format!("kicked from room {room}"));
+        // Full only when the connection is being closed already.
+        let _ = connection.try_send(Notice::Close(reason));
+    }
+    Ok(())
+}
@@ -12,9 +12,13 @@ pub fn task() -> Router {
         .route("/cookie", post(git_cookie_route))
 }
 
+fn invalid_archive(e: std::io::Error) -> AppError {
+    AppError::bad_request(format!("invalid tar archive: {e}"))
+}
+
 async fn archive_files_route(body: Bytes) -> Result<String, AppError> {
     let mut archive = Archive::new(body.reader());
-    let count = archive.entries().unwrap().count();
+    let count = archive.entries().map_err(invalid_archive)?.count();
     Ok(count.to_string())
 }
 
@@ -22,7 +26,7 @@ async fn archive_files_size_route(body: Bytes) -> Result<String, AppError> {
     let mut archive = Archive::new(body.reader());
     let total_size = archive
         .entries()
-        .unwrap()
+        .map_err(invalid_archive)?
         .filter_map(|entry| entry.ok())
         .map(|entry| entry.size())
         .sum::<u64>();
@@ -32,10 +36,15 @@ async fn archive_files_size_route(body: Bytes) -> Result<String, AppError> {
 
 async fn git_cookie_route(body: Bytes) -> anyhow::Result<String, AppError> {
     let temp_dir = tempfile::tempdir()?;
-    Archive::new(body.reader()).unpack(temp_dir.path())?;
+    Archive::new(body.reader())
+        .unpack(temp_dir.path())
+        .map_err(invalid_archive)?;
 
-    let repo = Repository::open(temp_dir.path()).unwrap();
-    let branch = repo.find_branch("christmas", git2::BranchType::Local)?;
+    let repo = Repository::open(temp_dir.path())
+        .map_err(|e| AppError::bad_request(format!("archive is not a git repository: {e}")))?;
+    let branch = repo
+        .find_branch("christmas", git2::BranchType::Local)
+        .map_err(|e| AppError::not_found(format!("no christmas branch: {e}")))?;
     let head_commit = branch.get().peel_to_commit()?;
     // info!("{:?}", head_commit);
 
@@ -46,11 +55,19 @@ async fn git_cookie_route(body: Bytes) -> anyhow::Result<String, AppError> {
 
         tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
             // info!("{:?}", entry.to_object(&repo));
-            if entry.name() == Some("santa.txt")
-                && std::str::from_utf8(entry.to_object(&repo).unwrap().as_blob().unwrap().content())
-                    .unwrap()
-                    .contains("COOKIE")
-            {
+            let is_cookie_blob = || {
+                entry
+                    .to_object(&repo)
+                    .ok()
+                    .and_then(|object| {
+                        object
+                            .as_blob()
+                            .map(|blob| String::from_utf8_lossy(blob.content()).contains("COOKIE"))
+                    })
+                    .unwrap_or(false)
+            };
+
+            if entry.name() == Some("santa.txt") && is_cookie_blob() {
                 find_cookie = true;
                 git2::TreeWalkResult::Abort
             } else {
@@ -61,7 +78,7 @@ async fn git_cookie_route(body: Bytes) -> anyhow::Result<String, AppError> {
             return {
                 Ok(format!(
                     "{} {}",
-                    commit.author().name().unwrap(),
+                    commit.author().name().unwrap_or_default(),
                     commit.id()
                 ))
             };
@@ -73,5 +90,5 @@ async fn git_cookie_route(body: Bytes) -> anyhow::Result<String, AppError> {
         }
     }
 
-    Err(anyhow::anyhow!("not found"))?
+    Err(AppError::not_found("no commit with a cookie in santa.txt"))
 }
@@ -10,9 +10,15 @@ pub fn task() -> Router {
         .route("/country/:id", get(country_route))
 }
 
+fn parse_cell_id(id: &str) -> Result<CellID, AppError> {
+    u64::from_str_radix(id, 2)
+        .map(CellID)
+        .map_err(|e| AppError::bad_request(format!("invalid binary cell id: {e}")))
+}
+
 async fn coords_route(Path(id): Path<String>) -> Result<String, AppError> {
-    let id = u64::from_str_radix(&id, 2)?;
-    let center = Cell::from(CellID(id)).center();
+    let id = parse_cell_id(&id)?;
+    let center = Cell::from(id).center();
 
     let lat = DMS::from_ddeg_latitude(center.latitude().deg());
     let lng = DMS::from_ddeg_longitude(center.longitude().deg());
@@ -31,8 +37,8 @@ async fn coords_route(Path(id): Path<String>) -> Result<String, AppError> {
 }
 
 async fn country_route(Path(id): Path<String>) -> Result<String, AppError> {
-    let id = u64::from_str_radix(&id, 2)?;
-    let center = Cell::from(CellID(id)).center();
+    let id = parse_cell_id(&id)?;
+    let center = Cell::from(id).center();
 
     let lat = center.latitude().deg();
     let lng = center.longitude().deg();
@@ -40,11 +46,14 @@ async fn country_route(Path(id): Path<String>) -> Result<String, AppError> {
     let boundaries = CountryBoundaries::from_reader(BOUNDARIES_ODBL_360X180)?;
     let ids = boundaries.ids(LatLon::new(lat, lng)?);
 
-    let country_id = ids.last().unwrap();
+    let country_id = ids
+        .last()
+        .ok_or_else(|| AppError::not_found("cell is not inside any country"))?;
     let country = isocountry::CountryCode::for_alpha2(country_id)?.name();
 
-    Ok(format!(
-        "{}",
-        country.split_ascii_whitespace().next().unwrap()
-    ))
+    Ok(country
+        .split_ascii_whitespace()
+        .next()
+        .unwrap_or(country)
+        .to_string())
 }
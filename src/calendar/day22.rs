@@ -1,5 +1,7 @@
+use std::str::FromStr;
+
 use super::error::AppError;
-use axum::{response::IntoResponse, routing::post, Router};
+use axum::{routing::post, Router};
 use glam::{IVec3, UVec2};
 use pathfinding::directed::bfs::bfs;
 
@@ -9,48 +11,72 @@ pub fn task() -> Router {
         .route("/rocket", post(rocket_route))
 }
 
+// Largest number of presents we are willing to wrap in a single response.
+const MAX_PRESENTS: u64 = 1 << 20;
+
 async fn integers_route(text: String) -> Result<String, AppError> {
     let num = text
         .lines()
         .map(|s| s.parse::<u64>())
         .try_fold(0u64, |acc, n| n.map(|n| acc ^ n))
-        .expect("cannot get value") as usize;
+        .map_err(|e| AppError::bad_request(format!("every line must be an integer: {e}")))?;
+
+    if num > MAX_PRESENTS {
+        return Err(AppError::bad_request(format!(
+            "refusing to wrap {num} presents, the limit is {MAX_PRESENTS}"
+        )));
+    }
+
+    Ok("🎁".repeat(num as usize))
+}
+
+fn parse_line<T: FromStr, const N: usize>(line: Option<&str>) -> Result<[T; N], AppError>
+where
+    T::Err: std::fmt::Display,
+{
+    let line = line.ok_or_else(|| AppError::bad_request("unexpected end of input"))?;
 
-    Ok("🎁".repeat(num))
+    line.split_ascii_whitespace()
+        .map(|num| {
+            num.parse::<T>()
+                .map_err(|e| AppError::bad_request(format!("invalid number {num:?}: {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .try_into()
+        .map_err(|_| AppError::bad_request(format!("expected {N} numbers in line {line:?}")))
 }
 
-async fn rocket_route(text: String) -> impl IntoResponse {
+async fn rocket_route(text: String) -> Result<String, AppError> {
     let mut input = text
         .lines()
         .map(|line| line.trim())
         .filter(|line| !line.is_empty());
 
-    let num_of_stars = input.next().unwrap().parse::<usize>().unwrap();
+    let [num_of_stars] = parse_line::<usize, 1>(input.next())?;
 
-    let map = input
-        .clone()
-        .take(num_of_stars)
-        .map(|line| {
-            line.split(' ')
-                .map(|num| num.parse::<i32>().unwrap())
-                .collect::<Vec<_>>()
-        })
-        .map(|coords| IVec3::new(coords[0], coords[1], coords[2]))
-        .collect::<Vec<_>>();
+    if num_of_stars == 0 {
+        return Err(AppError::bad_request("there must be at least one star"));
+    }
 
-    let mut input = input.skip(num_of_stars).clone();
+    let map = (0..num_of_stars)
+        .map(|_| parse_line::<i32, 3>(input.next()).map(IVec3::from_array))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let num_of_portals = input.next().unwrap().parse::<usize>().unwrap();
+    let [num_of_portals] = parse_line::<usize, 1>(input.next())?;
 
-    let portals = input
-        .take(num_of_portals)
-        .map(|line| {
-            line.split(' ')
-                .map(|num| num.parse::<u32>().unwrap())
-                .collect::<Vec<_>>()
-        })
-        .map(|connection| UVec2::new(connection[0], connection[1]))
-        .collect::<Vec<_>>();
+    let portals = (0..num_of_portals)
+        .map(|_| parse_line::<u32, 2>(input.next()).map(UVec2::from_array))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(portal) = portals
+        .iter()
+        .find(|p| p.max_element() as usize >= num_of_stars)
+    {
+        return Err(AppError::bad_request(format!(
+            "portal {} {} refers to an unknown star",
+            portal.x, portal.y
+        )));
+    }
 
     let path = bfs(
         &0,
@@ -63,11 +89,11 @@ async fn rocket_route(text: String) -> impl IntoResponse {
         },
         |p| *p == num_of_stars - 1,
     )
-    .unwrap();
+    .ok_or_else(|| AppError::not_found("no path leads to the last star"))?;
 
     let distance = path.windows(2).fold(0.0, |acc, p| {
         acc + ((map[p[0]] - map[p[1]]).length_squared() as f32).sqrt()
     });
 
-    format!("{} {:.3}", path.len() - 1, distance)
+    Ok(format!("{} {:.3}", path.len() - 1, distance))
 }
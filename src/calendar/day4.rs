@@ -1,4 +1,5 @@
-use axum::{http::StatusCode, routing::post, Json, Router};
+use super::error::AppError;
+use axum::{routing::post, Json, Router};
 use serde::{Deserialize, Serialize};
 
 pub fn task() -> Router {
@@ -41,9 +42,9 @@ struct ContestResult {
 
 async fn contest_route(
     Json(payload): Json<Vec<Reindeer>>,
-) -> Result<Json<ContestResult>, (StatusCode, &'static str)> {
+) -> Result<Json<ContestResult>, AppError> {
     if payload.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "No reindeer data provided"));
+        return Err(AppError::bad_request("No reindeer data provided"));
     }
 
     let fastest_reindeer = payload
@@ -1,3 +1,4 @@
+use super::error::AppError;
 use axum::{extract::Query, response::IntoResponse, routing::post, Json, Router};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -16,18 +17,18 @@ struct Pagination {
 async fn pager_route(
     Query(pagination): Query<Pagination>,
     Json(input): Json<Vec<String>>,
-) -> impl IntoResponse {
-    let offset = pagination.offset.unwrap_or(0);
+) -> Result<impl IntoResponse, AppError> {
+    let offset = pagination.offset.unwrap_or(0).min(input.len());
 
     let data = if let Some(limit) = pagination.limit {
-        input[offset..(offset + limit).min(input.len())].to_vec()
+        input[offset..offset.saturating_add(limit).min(input.len())].to_vec()
     } else {
         input[offset..].to_vec()
     };
 
-    if let Some(split_size) = pagination.split {
-        Json(json!(data.chunks(split_size).collect::<Vec<_>>()))
-    } else {
-        Json(json!(data.to_vec()))
+    match pagination.split {
+        Some(0) => Err(AppError::bad_request("split must be greater than zero")),
+        Some(split_size) => Ok(Json(json!(data.chunks(split_size).collect::<Vec<_>>()))),
+        None => Ok(Json(json!(data.to_vec()))),
     }
 }
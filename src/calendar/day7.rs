@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use super::error::AppError;
 use axum::{response::IntoResponse, routing::get, Json, Router};
 use axum_extra::extract::cookie::CookieJar;
 use base64::{engine::general_purpose, Engine as _};
@@ -13,24 +14,30 @@ pub fn task() -> Router {
         .route("/bake", get(bake_route))
 }
 
-async fn decode_route(jar: CookieJar) -> impl IntoResponse {
-    let recipe_value: String = jar
+fn recipe_cookie(jar: &CookieJar) -> Result<Vec<u8>, AppError> {
+    let recipe_value = jar
         .get("recipe")
-        .map_or_else(|| String::new(), |cookie| cookie.value().to_string());
+        .ok_or_else(|| AppError::bad_request("missing recipe cookie"))?
+        .value();
 
     info!("{:?}", recipe_value);
 
-    let decoded = general_purpose::STANDARD
+    general_purpose::STANDARD
         .decode(recipe_value)
-        .expect("cannot decode");
+        .map_err(|e| AppError::bad_request(format!("recipe cookie is not valid base64: {e}")))
+}
+
+async fn decode_route(jar: CookieJar) -> Result<impl IntoResponse, AppError> {
+    let decoded = recipe_cookie(&jar)?;
 
-    let utf8_string = String::from_utf8(decoded).expect("Invalid UTF-8");
+    let utf8_string = String::from_utf8(decoded)
+        .map_err(|e| AppError::bad_request(format!("recipe is not valid UTF-8: {e}")))?;
     info!("{:?}", utf8_string);
 
-    let json: serde_json::Value =
-        serde_json::from_str(&utf8_string).expect("cannot convert to json");
+    let json: serde_json::Value = serde_json::from_str(&utf8_string)
+        .map_err(|e| AppError::bad_request(format!("recipe is not valid JSON: {e}")))?;
 
-    Json(json)
+    Ok(Json(json))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -39,13 +46,11 @@ struct Bake {
     pantry: HashMap<String, i64>,
 }
 
-async fn bake_route(jar: CookieJar) -> impl IntoResponse {
-    let encoded_recipe = jar.get("recipe").unwrap();
-    let decoded_recipe = general_purpose::STANDARD
-        .decode(encoded_recipe.value())
-        .unwrap();
+async fn bake_route(jar: CookieJar) -> Result<impl IntoResponse, AppError> {
+    let decoded_recipe = recipe_cookie(&jar)?;
 
-    let mut bake = serde_json::from_slice::<Bake>(&decoded_recipe).unwrap();
+    let mut bake = serde_json::from_slice::<Bake>(&decoded_recipe)
+        .map_err(|e| AppError::bad_request(format!("invalid recipe: {e}")))?;
 
     let cookie_count = bake
         .recipe
@@ -68,5 +73,7 @@ async fn bake_route(jar: CookieJar) -> impl IntoResponse {
         }
     }
 
-    Json(json!({"cookies": cookie_count, "pantry": bake.pantry}))
+    Ok(Json(
+        json!({"cookies": cookie_count, "pantry": bake.pantry}),
+    ))
 }
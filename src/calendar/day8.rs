@@ -1,38 +1,76 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use axum::{extract::Path, routing::get, Router};
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Router,
+};
 
 use reqwest;
 
 use super::error::AppError;
 use anyhow;
 
+const POKEAPI_URL: &str = "https://pokeapi.co";
+
 pub fn task() -> Router {
+    task_with_api(POKEAPI_URL)
+}
+
+// Same as `task`, but talks to the PokeAPI served at `base_url`.
+pub fn task_with_api(base_url: impl Into<Arc<str>>) -> Router {
     Router::new()
         .route("/weight/:id", get(pokedex_route))
         .route("/drop/:id", get(pokedex_drop_route))
+        .with_state(base_url.into())
 }
 
-async fn pokedex_get(id: u32) -> anyhow::Result<HashMap<String, serde_json::Value>> {
-    let url = format!("https://pokeapi.co/api/v2/pokemon/{id}");
-    let res = reqwest::get(url).await?.json().await?;
+async fn pokedex_get(
+    base_url: &str,
+    id: u32,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let url = format!("{base_url}/api/v2/pokemon/{id}");
+    let res = reqwest::get(url).await?.error_for_status()?.json().await?;
 
     Ok(res)
 }
 
-async fn pokedex_route(id: Path<u32>) -> Result<String, AppError> {
-    let res = pokedex_get(id.0).await?;
-    let weight = res.get("weight").unwrap().as_f64().unwrap();
+// Weight of the pokemon in hectograms, as reported by the PokeAPI.
+async fn pokedex_weight(base_url: &str, id: u32) -> Result<f64, AppError> {
+    let res = match pokedex_get(base_url, id).await {
+        Ok(res) => res,
+        Err(e) => match e.downcast::<reqwest::Error>() {
+            Ok(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+                return Err(AppError::not_found(format!("no pokemon with id {id}")))
+            }
+            // Keep the upstream URL out of the response.
+            Ok(e) => return Err(AppError::upstream(e.without_url())),
+            Err(e) => return Err(AppError::upstream(e)),
+        },
+    };
+
+    res.get("weight")
+        .and_then(|weight| weight.as_f64())
+        .ok_or_else(|| AppError::upstream(anyhow::anyhow!("PokeAPI response has no weight")))
+}
+
+async fn pokedex_route(
+    id: Path<u32>,
+    State(base_url): State<Arc<str>>,
+) -> Result<String, AppError> {
+    let weight = pokedex_weight(&base_url, id.0).await?;
 
     Ok(format!("{}", weight / 10.0))
 }
 
-async fn pokedex_drop_route(id: Path<u32>) -> Result<String, AppError> {
+async fn pokedex_drop_route(
+    id: Path<u32>,
+    State(base_url): State<Arc<str>>,
+) -> Result<String, AppError> {
     const GRAVITY: f64 = 9.825;
     const CHIMNEY_HEIGHT: f64 = 10.0;
 
-    let res = pokedex_get(id.0).await?;
-    let weight = res.get("weight").unwrap().as_f64().unwrap();
+    let weight = pokedex_weight(&base_url, id.0).await?;
 
     let velocity = (2.0 * GRAVITY * CHIMNEY_HEIGHT).sqrt();
     let momentum = weight * velocity / 10.0;
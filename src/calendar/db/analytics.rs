@@ -0,0 +1,114 @@
+use sqlx::{database::HasArguments, Database, Encode, QueryBuilder, Type};
+
+use super::{AnalyticsQuery, Direction, SortKey};
+
+// SQL behind `OrderStore::analytics`, shared by the Postgres and SQLite
+// stores. Only fixed fragments are pushed as text, every value coming from
+// the client is bound.
+
+// One page of groups, decoding into `AnalyticsRow`.
+pub(super) fn page<'args, DB>(query: &AnalyticsQuery) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'args>>::Arguments: Default,
+    i32: Encode<'args, DB> + Type<DB>,
+    i64: Encode<'args, DB> + Type<DB>,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    let mut sql = QueryBuilder::new("");
+    push_groups(&mut sql, query);
+
+    sql.push(" ORDER BY ");
+    sql.push(match query.sort {
+        SortKey::Region => "region",
+        SortKey::Gift => "gift",
+        SortKey::Total => "total",
+        SortKey::Orders => "orders",
+    });
+    sql.push(match query.direction {
+        Direction::Asc => " ASC",
+        Direction::Desc => " DESC",
+    });
+    sql.push(", region, region_id, gift LIMIT ");
+    sql.push_bind(query.limit);
+    sql.push(" OFFSET ");
+    sql.push_bind(query.offset);
+
+    sql
+}
+
+// Number of groups, total quantity and number of orders across every page.
+pub(super) fn totals<'args, DB>(query: &AnalyticsQuery) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'args>>::Arguments: Default,
+    i32: Encode<'args, DB> + Type<DB>,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    let mut sql = QueryBuilder::new(
+        "SELECT COUNT(*),
+          CAST(COALESCE(SUM(total), 0) AS BIGINT),
+          CAST(COALESCE(SUM(orders), 0) AS BIGINT)
+        FROM (",
+    );
+    push_groups(&mut sql, query);
+    sql.push(") AS grouped");
+
+    sql
+}
+
+fn push_groups<'args, DB>(sql: &mut QueryBuilder<'args, DB>, query: &AnalyticsQuery)
+where
+    DB: Database,
+    i32: Encode<'args, DB> + Type<DB>,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    sql.push("SELECT ");
+    sql.push(if query.by_region {
+        "r.id AS region_id, r.name AS region, "
+    } else {
+        "CAST(NULL AS INTEGER) AS region_id, CAST(NULL AS TEXT) AS region, "
+    });
+    sql.push(if query.by_gift {
+        "o.gift_name AS gift, "
+    } else {
+        "CAST(NULL AS TEXT) AS gift, "
+    });
+    sql.push(
+        "CAST(COALESCE(SUM(o.quantity), 0) AS BIGINT) AS total, COUNT(*) AS orders
+        FROM orders o JOIN regions r ON r.id = o.region_id
+        WHERE 1 = 1",
+    );
+
+    if let Some(region_ids) = &query.region_ids {
+        sql.push(" AND o.region_id IN (");
+        let mut ids = sql.separated(", ");
+        for id in region_ids {
+            ids.push_bind(*id);
+        }
+        sql.push(")");
+    }
+    if let Some(prefix) = &query.gift_prefix {
+        // Unlike LIKE, this needs no escaping and is case sensitive on both
+        // databases.
+        sql.push(" AND substr(o.gift_name, 1, length(");
+        sql.push_bind(prefix.clone());
+        sql.push(")) = ");
+        sql.push_bind(prefix.clone());
+    }
+    if let Some(min) = query.min_quantity {
+        sql.push(" AND o.quantity >= ");
+        sql.push_bind(min);
+    }
+    if let Some(max) = query.max_quantity {
+        sql.push(" AND o.quantity <= ");
+        sql.push_bind(max);
+    }
+
+    match (query.by_region, query.by_gift) {
+        (true, true) => sql.push(" GROUP BY r.id, r.name, o.gift_name"),
+        (true, false) => sql.push(" GROUP BY r.id, r.name"),
+        (false, true) => sql.push(" GROUP BY o.gift_name"),
+        (false, false) => sql,
+    };
+}
@@ -0,0 +1,767 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+
+use super::{
+    check_parents, check_regions, check_stock, cut_ranking, gift_exists, missing_regions,
+    plan_batch, stock_demand, Analytics, AnalyticsQuery, AnalyticsRow, AnalyticsTotals,
+    BatchReport, Bucket, Capsule, CapsuleCallback, CapsuleStore, ChatMessage, ChatStore, ChatViews,
+    Direction, Gift, IngestParams, OnConflict, Order, OrderPages, OrderStore, PopularQuery,
+    RankedGift, Region, RegionRollup, RegionTotal, Row, SelectOutput, SortKey, TimeRange, TopList,
+    EXPORT_PAGE_SIZE,
+};
+use crate::calendar::error::AppError;
+
+// Order store living in process memory, handy for tests and laptops.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Arc<RwLock<Tables>>,
+}
+
+#[derive(Default)]
+struct Tables {
+    orders: BTreeMap<i32, Order>,
+    // Stock reserved by each order, none for those missing.
+    reserved: HashMap<i32, i32>,
+    regions: BTreeMap<i32, Region>,
+    gifts: BTreeMap<String, Gift>,
+    capsules: BTreeMap<String, Capsule>,
+    // Callbacks by id, along with when they are due.
+    callbacks: BTreeMap<i64, (DateTime<Utc>, CapsuleCallback)>,
+    // History of every chat room, oldest message first.
+    chat: HashMap<i32, VecDeque<ChatMessage>>,
+    last_chat_id: i64,
+    // Views by room and user.
+    chat_views: BTreeMap<(i32, String), i64>,
+}
+
+impl Tables {
+    // Total quantity per gift for the orders matching `filter`, most ordered
+    // first. Ties are broken by gift name to keep the output stable.
+    fn gift_totals(&self, filter: impl Fn(&Order) -> bool) -> Vec<(String, i64)> {
+        let mut totals = HashMap::<&str, i64>::new();
+        for order in self.orders.values().filter(|order| filter(order)) {
+            *totals.entry(&order.gift_name).or_default() += order.quantity as i64;
+        }
+
+        let mut totals = totals
+            .into_iter()
+            .map(|(gift, total)| (gift.to_string(), total))
+            .collect::<Vec<_>>();
+        totals.sort_by(|(a_gift, a_total), (b_gift, b_total)| {
+            (Reverse(a_total), a_gift).cmp(&(Reverse(b_total), b_gift))
+        });
+        totals
+    }
+}
+
+fn placed_within(order: &Order, range: &TimeRange) -> bool {
+    order.created_at.is_some_and(|time| range.contains(time))
+}
+
+// Whether `order` passes the filters of `query`.
+fn matches(query: &AnalyticsQuery, order: &Order) -> bool {
+    query
+        .region_ids
+        .as_ref()
+        .is_none_or(|ids| ids.contains(&order.region_id))
+        && query
+            .gift_prefix
+            .as_ref()
+            .is_none_or(|prefix| order.gift_name.starts_with(prefix.as_str()))
+        && query.min_quantity.is_none_or(|min| order.quantity >= min)
+        && query.max_quantity.is_none_or(|max| order.quantity <= max)
+}
+
+// Order of analytics rows, matching the `ORDER BY` of the SQL stores.
+fn compare_rows(query: &AnalyticsQuery, a: &AnalyticsRow, b: &AnalyticsRow) -> Ordering {
+    let key = match query.sort {
+        SortKey::Region => a.region.cmp(&b.region),
+        SortKey::Gift => a.gift.cmp(&b.gift),
+        SortKey::Total => a.total.cmp(&b.total),
+        SortKey::Orders => a.orders.cmp(&b.orders),
+    };
+    let key = match query.direction {
+        Direction::Asc => key,
+        Direction::Desc => key.reverse(),
+    };
+
+    key.then_with(|| (&a.region, a.region_id, &a.gift).cmp(&(&b.region, b.region_id, &b.gift)))
+}
+
+// Ids of `batch` already present in `table`.
+fn existing_ids<T>(table: &BTreeMap<i32, T>, batch: impl Iterator<Item = i32>) -> HashSet<i32> {
+    batch.filter(|id| table.contains_key(id)).collect()
+}
+
+#[async_trait]
+impl OrderStore for MemoryStore {
+    async fn ping(&self, number: i32) -> Result<i32, AppError> {
+        Ok(number)
+    }
+
+    async fn reset_orders(&self) -> Result<(), AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        tables.orders.clear();
+        tables.reserved.clear();
+        Ok(())
+    }
+
+    async fn reset_all(&self) -> Result<(), AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        tables.orders.clear();
+        tables.reserved.clear();
+        tables.regions.clear();
+        tables.gifts.clear();
+        Ok(())
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        params: &IngestParams,
+    ) -> Result<BatchReport, AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        let on_conflict = params.on_conflict;
+
+        let existing = existing_ids(&tables.orders, orders.iter().map(|order| order.id));
+        let (rows, report) = plan_batch(orders, &existing, on_conflict)?;
+        let mut known = existing_ids(&tables.regions, rows.iter().map(|order| order.region_id));
+        let created = if params.create_regions {
+            missing_regions(&rows, &known)
+        } else {
+            Vec::new()
+        };
+        known.extend(created.iter().map(|region| region.id));
+        check_regions(&rows, &known)?;
+
+        // Check every sum before touching the tables so a failure leaves them
+        // as they were.
+        let mut stock = Vec::new();
+        let stored = rows
+            .iter()
+            .filter_map(|order| tables.orders.get(&order.id))
+            .map(|order| {
+                let reserved = tables.reserved.get(&order.id).copied().unwrap_or(0);
+                (order.id, (order.gift_name.clone(), reserved))
+            })
+            .collect();
+        let demand = stock_demand(&rows, &stored, params);
+        let available = demand
+            .keys()
+            .filter_map(|name| tables.gifts.get(name))
+            .map(|gift| (gift.name.clone(), gift.stock))
+            .collect::<HashMap<_, _>>();
+        check_stock(&demand, &available)?;
+
+        for (name, amount) in demand {
+            let Some(&current) = available.get(&name) else {
+                continue;
+            };
+            let left = i32::try_from(current as i64 - amount)
+                .map_err(|_| AppError::bad_request(format!("stock of gift {name:?} overflows")))?;
+            stock.push((name, left));
+        }
+
+        let mut writes = Vec::with_capacity(rows.len());
+        for mut order in rows {
+            let mut reserved = params.reserved(&order);
+            if let (OnConflict::AddQuantity, Some(stored)) =
+                (on_conflict, tables.orders.get(&order.id))
+            {
+                let mut stored = stored.clone();
+                stored.add_quantity(&order)?;
+                order = stored;
+                reserved += tables.reserved.get(&order.id).copied().unwrap_or(0);
+            }
+            writes.push((order, reserved));
+        }
+
+        for region in created {
+            tables.regions.insert(region.id, region);
+        }
+        for (order, reserved) in writes {
+            tables.reserved.insert(order.id, reserved);
+            tables.orders.insert(order.id, order);
+        }
+        for (name, left) in stock {
+            if let Some(gift) = tables.gifts.get_mut(&name) {
+                gift.stock = left;
+            }
+        }
+        Ok(report)
+    }
+
+    async fn insert_regions(
+        &self,
+        regions: &[Region],
+        on_conflict: OnConflict,
+    ) -> Result<BatchReport, AppError> {
+        let tables = &mut self.tables.write().unwrap();
+
+        let existing = existing_ids(&tables.regions, regions.iter().map(|region| region.id));
+        let (rows, report) = plan_batch(regions, &existing, on_conflict)?;
+        let parents = tables
+            .regions
+            .values()
+            .map(|region| (region.id, region.parent_id))
+            .collect();
+        check_parents(&rows, &parents)?;
+
+        for region in rows {
+            tables.regions.insert(region.id, region);
+        }
+        Ok(report)
+    }
+
+    async fn total_quantity(&self, range: &TimeRange) -> Result<i64, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .orders
+            .values()
+            .filter(|order| placed_within(order, range))
+            .map(|order| order.quantity as i64)
+            .sum())
+    }
+
+    async fn popular_gifts(&self, query: &PopularQuery) -> Result<Vec<RankedGift>, AppError> {
+        let tables = self.tables.read().unwrap();
+        let totals = tables.gift_totals(|order| {
+            placed_within(order, &query.range)
+                && query.region_id.is_none_or(|id| order.region_id == id)
+        });
+
+        let mut gifts = Vec::<RankedGift>::new();
+        for (gift, quantity) in totals {
+            let rank = match gifts.last() {
+                Some(last) if last.quantity == quantity => last.rank,
+                Some(last) => last.rank + 1,
+                None => 1,
+            };
+            gifts.push(RankedGift {
+                rank,
+                gift,
+                quantity,
+            });
+        }
+
+        Ok(cut_ranking(gifts, query))
+    }
+
+    async fn total_series(
+        &self,
+        range: &TimeRange,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, i64)>, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        let mut series = BTreeMap::<DateTime<Utc>, i64>::new();
+        for order in tables.orders.values() {
+            if let Some(created_at) = order.created_at.filter(|&time| range.contains(time)) {
+                *series.entry(bucket.start(created_at)).or_default() += order.quantity as i64;
+            }
+        }
+
+        Ok(series.into_iter().collect())
+    }
+
+    async fn popular_series(
+        &self,
+        range: &TimeRange,
+        region_id: Option<i32>,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, String)>, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        let starts = tables
+            .orders
+            .values()
+            .filter_map(|order| order.created_at.filter(|&time| range.contains(time)))
+            .map(|created_at| bucket.start(created_at))
+            .collect::<BTreeSet<_>>();
+
+        Ok(starts
+            .into_iter()
+            .filter_map(|start| {
+                let within = TimeRange {
+                    from: Some(start),
+                    to: Some(bucket.next(start)),
+                };
+                let (gift, _) = tables
+                    .gift_totals(|order| {
+                        placed_within(order, range)
+                            && placed_within(order, &within)
+                            && region_id.is_none_or(|id| order.region_id == id)
+                    })
+                    .into_iter()
+                    .next()?;
+                Some((start, gift))
+            })
+            .collect())
+    }
+
+    async fn export_orders(&self) -> Result<OrderPages, AppError> {
+        let tables = Arc::clone(&self.tables);
+        let pages = stream::unfold(Some(Bound::Unbounded), move |after| {
+            let tables = Arc::clone(&tables);
+            async move {
+                let page = tables
+                    .read()
+                    .unwrap()
+                    .orders
+                    .range((after?, Bound::Unbounded))
+                    .take(EXPORT_PAGE_SIZE)
+                    .map(|(_, order)| order.clone())
+                    .collect::<Vec<_>>();
+                let last = page.last()?.id;
+                let after = (page.len() == EXPORT_PAGE_SIZE).then_some(Bound::Excluded(last));
+                Some((Ok(page), after))
+            }
+        });
+
+        Ok(pages.boxed())
+    }
+
+    async fn gifts(&self) -> Result<Vec<Gift>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables.gifts.values().cloned().collect())
+    }
+
+    async fn gift(&self, name: &str) -> Result<Option<Gift>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables.gifts.get(name).cloned())
+    }
+
+    async fn create_gift(&self, gift: &Gift) -> Result<(), AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        if tables.gifts.contains_key(&gift.name) {
+            return Err(gift_exists(&gift.name));
+        }
+
+        tables.gifts.insert(gift.name.clone(), gift.clone());
+        Ok(())
+    }
+
+    async fn put_gift(&self, gift: &Gift) -> Result<bool, AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        Ok(tables
+            .gifts
+            .insert(gift.name.clone(), gift.clone())
+            .is_none())
+    }
+
+    async fn delete_gift(&self, name: &str) -> Result<bool, AppError> {
+        let tables = &mut self.tables.write().unwrap();
+        Ok(tables.gifts.remove(name).is_some())
+    }
+
+    async fn low_stock(&self, threshold: Option<i32>) -> Result<Vec<Gift>, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        let mut gifts = tables
+            .gifts
+            .values()
+            .filter(|gift| gift.stock <= threshold.unwrap_or(gift.low_stock_threshold))
+            .cloned()
+            .collect::<Vec<_>>();
+        gifts.sort_by(|a, b| (a.stock, &a.name).cmp(&(b.stock, &b.name)));
+        Ok(gifts)
+    }
+
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        let mut totals = BTreeMap::<&str, i64>::new();
+        for order in tables.orders.values() {
+            if let Some(region) = tables.regions.get(&order.region_id) {
+                *totals.entry(&region.name).or_default() += order.quantity as i64;
+            }
+        }
+
+        Ok(totals
+            .into_iter()
+            .map(|(region, total)| RegionTotal {
+                region: region.to_string(),
+                total,
+            })
+            .collect())
+    }
+
+    async fn region_rollups(&self, depth: i32) -> Result<Vec<RegionRollup>, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        // Every order counts towards its region and each region above it.
+        let mut totals = HashMap::<i32, i64>::new();
+        for order in tables.orders.values() {
+            let mut region_id = Some(order.region_id);
+            while let Some(region) = region_id.and_then(|id| tables.regions.get(&id)) {
+                *totals.entry(region.id).or_default() += order.quantity as i64;
+                region_id = region.parent_id;
+            }
+        }
+
+        let mut rollups = Vec::new();
+        let mut level = tables
+            .regions
+            .values()
+            .filter(|region| region.parent_id.is_none())
+            .collect::<Vec<_>>();
+        for depth in 0..depth {
+            if level.is_empty() {
+                break;
+            }
+
+            let ids = level.iter().map(|region| region.id).collect::<HashSet<_>>();
+            rollups.extend(level.into_iter().map(|region| RegionRollup {
+                id: region.id,
+                name: region.name.clone(),
+                parent_id: region.parent_id,
+                level: depth,
+                total: totals.get(&region.id).copied().unwrap_or_default(),
+            }));
+            level = tables
+                .regions
+                .values()
+                .filter(|region| region.parent_id.is_some_and(|id| ids.contains(&id)))
+                .collect();
+        }
+
+        Ok(rollups)
+    }
+
+    async fn top_lists(&self, limit: i64) -> Result<Vec<TopList>, AppError> {
+        let tables = self.tables.read().unwrap();
+        let limit = usize::try_from(limit)
+            .map_err(|_| AppError::bad_request("LIMIT must not be negative"))?;
+
+        // Regions sharing a name are reported together, like `GROUP BY name`.
+        let mut top_lists = BTreeMap::<&str, Vec<String>>::new();
+        for region in tables.regions.values() {
+            let top_gifts = tables
+                .gift_totals(|order| order.region_id == region.id)
+                .into_iter()
+                .take(limit)
+                .map(|(gift, _)| gift);
+            top_lists.entry(&region.name).or_default().extend(top_gifts);
+        }
+
+        Ok(top_lists
+            .into_iter()
+            .map(|(region, top_gifts)| TopList {
+                region: region.to_string(),
+                top_gifts,
+            })
+            .collect())
+    }
+
+    async fn analytics(&self, query: &AnalyticsQuery) -> Result<Analytics, AppError> {
+        let tables = self.tables.read().unwrap();
+
+        let mut groups = BTreeMap::<(Option<i32>, Option<&str>), AnalyticsRow>::new();
+        // Without any dimension everything is summed up into one row, even
+        // when no order matches.
+        if !query.by_region && !query.by_gift {
+            groups.insert((None, None), AnalyticsRow::default());
+        }
+
+        for order in tables.orders.values().filter(|order| matches(query, order)) {
+            let Some(region) = tables.regions.get(&order.region_id) else {
+                continue;
+            };
+
+            let key = (
+                query.by_region.then_some(region.id),
+                query.by_gift.then_some(order.gift_name.as_str()),
+            );
+            let row = groups.entry(key).or_insert_with(|| AnalyticsRow {
+                region_id: key.0,
+                region: query.by_region.then(|| region.name.clone()),
+                gift: key.1.map(str::to_string),
+                ..AnalyticsRow::default()
+            });
+            row.total += order.quantity as i64;
+            row.orders += 1;
+        }
+
+        let mut rows = groups.into_values().collect::<Vec<_>>();
+        let totals = AnalyticsTotals {
+            groups: rows.len() as i64,
+            total: rows.iter().map(|row| row.total).sum(),
+            orders: rows.iter().map(|row| row.orders).sum(),
+        };
+
+        rows.sort_by(|a, b| compare_rows(query, a, b));
+        let rows = rows
+            .into_iter()
+            .skip(query.offset.max(0) as usize)
+            .take(query.limit.max(0) as usize)
+            .collect();
+
+        Ok(Analytics { rows, totals })
+    }
+
+    async fn run_select(&self, _sql: &str, _max_rows: usize) -> Result<SelectOutput, AppError> {
+        Err(AppError::not_implemented(
+            "SQL can only be run against a Postgres or SQLite store",
+        ))
+    }
+}
+
+#[async_trait]
+impl CapsuleStore for MemoryStore {
+    async fn save_capsule(&self, capsule: &Capsule) -> Result<(), AppError> {
+        let capsules = &mut self.tables.write().unwrap().capsules;
+        // Capsules owing a callback stay until it is queued.
+        capsules
+            .retain(|_, stored| !stored.is_expired(capsule.saved_at) || stored.callback.is_some());
+        capsules.insert(capsule.key.clone(), capsule.clone());
+        Ok(())
+    }
+
+    async fn capsule(&self, key: &str, now: DateTime<Utc>) -> Result<Option<Capsule>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .capsules
+            .get(key)
+            .filter(|capsule| !capsule.is_expired(now))
+            .cloned())
+    }
+
+    async fn capsules(&self, now: DateTime<Utc>) -> Result<Vec<Capsule>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .capsules
+            .values()
+            .filter(|capsule| !capsule.is_expired(now))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_capsule(&self, key: &str, now: DateTime<Utc>) -> Result<bool, AppError> {
+        let capsules = &mut self.tables.write().unwrap().capsules;
+        let live = capsules
+            .get(key)
+            .is_some_and(|capsule| !capsule.is_expired(now));
+        if live {
+            capsules.remove(key);
+        }
+        Ok(live)
+    }
+
+    async fn fire_capsules(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let tables = &mut *self.tables.write().unwrap();
+        let due = tables
+            .capsules
+            .values()
+            .filter(|capsule| capsule.is_due(now))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for capsule in &due {
+            let id = tables
+                .callbacks
+                .last_key_value()
+                .map_or(1, |(id, _)| id + 1);
+            let callback = CapsuleCallback {
+                id,
+                key: capsule.key.clone(),
+                url: capsule.callback.clone().unwrap_or_default(),
+                deadline: capsule.expires_at.unwrap_or(now),
+                payload: capsule.payload.clone(),
+                attempts: 0,
+            };
+            tables.callbacks.insert(id, (now, callback));
+
+            if capsule.every_secs.is_some() {
+                tables.capsules.get_mut(&capsule.key).unwrap().expires_at =
+                    capsule.next_deadline(now);
+            } else {
+                tables.capsules.remove(&capsule.key);
+            }
+        }
+
+        Ok(due.len())
+    }
+
+    async fn claim_callbacks(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<CapsuleCallback>, AppError> {
+        let callbacks = &mut self.tables.write().unwrap().callbacks;
+        let mut due = callbacks
+            .values_mut()
+            .filter(|(due_at, _)| *due_at <= now)
+            .collect::<Vec<_>>();
+        due.sort_by_key(|(due_at, _)| *due_at);
+
+        Ok(due
+            .into_iter()
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(|(due_at, callback)| {
+                *due_at = lease_until;
+                callback.attempts += 1;
+                callback.clone()
+            })
+            .collect())
+    }
+
+    async fn retry_callback(&self, id: i64, due_at: DateTime<Utc>) -> Result<(), AppError> {
+        if let Some(callback) = self.tables.write().unwrap().callbacks.get_mut(&id) {
+            callback.0 = due_at;
+        }
+        Ok(())
+    }
+
+    async fn finish_callback(&self, id: i64) -> Result<(), AppError> {
+        self.tables.write().unwrap().callbacks.remove(&id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatStore for MemoryStore {
+    async fn append_chat(
+        &self,
+        room: i32,
+        user: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        keep: i64,
+    ) -> Result<ChatMessage, AppError> {
+        let tables = &mut *self.tables.write().unwrap();
+        tables.last_chat_id += 1;
+        let message = ChatMessage {
+            id: tables.last_chat_id,
+            room,
+            user: user.to_string(),
+            message: message.to_string(),
+            sent_at,
+        };
+
+        let history = tables.chat.entry(room).or_default();
+        history.push_back(message.clone());
+        let keep = usize::try_from(keep).unwrap_or(0);
+        while history.len() > keep {
+            history.pop_front();
+        }
+        Ok(message)
+    }
+
+    async fn chat_history(
+        &self,
+        room: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let tables = self.tables.read().unwrap();
+        let Some(history) = tables.chat.get(&room) else {
+            return Ok(Vec::new());
+        };
+
+        let mut messages = history
+            .iter()
+            .rev()
+            .filter(|message| before.is_none_or(|before| message.id < before))
+            .take(usize::try_from(limit).unwrap_or(0))
+            .cloned()
+            .collect::<Vec<_>>();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn chat_after(
+        &self,
+        room: i32,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .chat
+            .get(&room)
+            .into_iter()
+            .flatten()
+            .filter(|message| message.id > after)
+            .take(usize::try_from(limit).unwrap_or(0))
+            .cloned()
+            .collect())
+    }
+
+    async fn chat_message(&self, room: i32, id: i64) -> Result<Option<ChatMessage>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .chat
+            .get(&room)
+            .and_then(|history| history.iter().find(|message| message.id == id))
+            .cloned())
+    }
+
+    async fn edit_chat(&self, room: i32, id: i64, message: &str) -> Result<(), AppError> {
+        let mut tables = self.tables.write().unwrap();
+        if let Some(edited) = tables
+            .chat
+            .get_mut(&room)
+            .and_then(|history| history.iter_mut().find(|message| message.id == id))
+        {
+            edited.message = message.to_string();
+        }
+        Ok(())
+    }
+
+    async fn delete_chat(&self, room: i32, id: i64) -> Result<(), AppError> {
+        let mut tables = self.tables.write().unwrap();
+        if let Some(history) = tables.chat.get_mut(&room) {
+            history.retain(|message| message.id != id);
+        }
+        Ok(())
+    }
+
+    async fn reset_chat(&self) -> Result<(), AppError> {
+        self.tables.write().unwrap().chat.clear();
+        Ok(())
+    }
+
+    async fn chat_views(&self) -> Result<Vec<ChatViews>, AppError> {
+        let tables = self.tables.read().unwrap();
+        Ok(tables
+            .chat_views
+            .iter()
+            .map(|((room, user), &views)| ChatViews {
+                room: *room,
+                user: user.clone(),
+                views,
+            })
+            .collect())
+    }
+
+    async fn add_chat_views(&self, views: &[ChatViews]) -> Result<(), AppError> {
+        let mut tables = self.tables.write().unwrap();
+        for added in views {
+            *tables
+                .chat_views
+                .entry((added.room, added.user.clone()))
+                .or_default() += added.views;
+        }
+        Ok(())
+    }
+
+    async fn reset_chat_views(
+        &self,
+        room: Option<i32>,
+        user: Option<&str>,
+    ) -> Result<(), AppError> {
+        self.tables.write().unwrap().chat_views.retain(|(r, u), _| {
+            !(room.is_none_or(|room| room == *r) && user.is_none_or(|user| user == u))
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,1093 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Days, Months, TimeDelta, Utc};
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+use super::error::AppError;
+use super::export::Columns;
+
+mod analytics;
+mod memory;
+mod playground;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+// Handle to the store selected at startup, shared by days 12, 13, 18 and 19.
+pub type Store = Arc<dyn Backend>;
+
+// Everything a store keeps, implemented for Postgres, SQLite and plain process
+// memory so the stored days can run without a live Postgres.
+pub trait Backend: OrderStore + CapsuleStore + ChatStore {}
+
+impl<T: OrderStore + CapsuleStore + ChatStore> Backend for T {}
+
+// Largest request body accepted by the ingestion routes, big enough for
+// nightly imports of tens of thousands of orders.
+pub const MAX_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+// Orders fetched at a time by `OrderStore::export_orders`.
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+// Longest a statement of `OrderStore::run_select` may run.
+const PLAYGROUND_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Every stored order, sorted by id, one page at a time.
+pub type OrderPages = BoxStream<'static, Result<Vec<Order>, AppError>>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct Order {
+    pub id: i32,
+    pub region_id: i32,
+    pub gift_name: String,
+    pub quantity: i32,
+    // When the order was placed, the time it is stored at if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Columns for Order {
+    const COLUMNS: &'static [&'static str] =
+        &["id", "region_id", "gift_name", "quantity", "created_at"];
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Region {
+    pub id: i32,
+    pub name: String,
+    // Region this one is part of, none for the top of the tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i32>,
+}
+
+// Orders placed from `from` included up to `to` excluded. A missing bound
+// leaves that side open.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| from <= time) && self.to.is_none_or(|to| time < to)
+    }
+}
+
+// Span of time series points are summed over. Weeks start on Monday and every
+// bucket is aligned in UTC.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    // Name of the bucket as understood by Postgres' `date_trunc`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+
+    // Start of the bucket `time` falls in.
+    pub fn start(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = time.date_naive();
+        let date = match self {
+            Self::Day => date,
+            Self::Week => date - Days::new(date.weekday().num_days_from_monday().into()),
+            Self::Month => date.with_day(1).unwrap(),
+        };
+        date.and_time(Default::default()).and_utc()
+    }
+
+    // Start of the bucket following the one starting at `start`.
+    pub fn next(self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Day => start + Days::new(1),
+            Self::Week => start + Days::new(7),
+            Self::Month => start + Months::new(1),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct Gift {
+    pub name: String,
+    pub stock: i32,
+    // Stock at or below which the gift shows up as running low.
+    #[serde(default)]
+    pub low_stock_threshold: i32,
+}
+
+impl Gift {
+    // Constraint of the catalog broken by this gift, if any.
+    pub fn violation(&self) -> Option<&'static str> {
+        if self.name.is_empty() || self.name.chars().count() > 50 {
+            Some("name must be between 1 and 50 characters long")
+        } else if self.stock < 0 {
+            Some("stock must not be negative")
+        } else if self.low_stock_threshold < 0 {
+            Some("low_stock_threshold must not be negative")
+        } else {
+            None
+        }
+    }
+}
+
+// Ranking asked of `OrderStore::popular_gifts`.
+#[derive(Debug, Clone)]
+pub struct PopularQuery {
+    pub range: TimeRange,
+    pub region_id: Option<i32>,
+    pub limit: i64,
+    pub ties: Ties,
+}
+
+// Whether a ranking cut at its limit goes on with the gifts tied with the
+// last one it kept.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ties {
+    #[default]
+    Exclude,
+    Include,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct RankedGift {
+    pub rank: i64,
+    pub gift: String,
+    pub quantity: i64,
+}
+
+// What to do with rows whose id is already stored or repeats within a batch.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    // Reject the whole batch.
+    #[default]
+    Error,
+    // Keep the stored row and drop the new one.
+    Skip,
+    // Overwrite the stored row with the new one.
+    Replace,
+    // Add the new quantity to the stored one. Only orders have a quantity.
+    AddQuantity,
+}
+
+// Query string of the ingestion routes.
+#[derive(Deserialize, Debug, Default)]
+pub struct IngestParams {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    // Whether orders take their quantity out of the stock of their gift.
+    #[serde(default)]
+    pub reserve_stock: bool,
+    // Whether regions the orders refer to are created when missing, named
+    // after their id. Set by the routes themselves, never by the query
+    // string.
+    #[serde(skip)]
+    pub create_regions: bool,
+}
+
+impl IngestParams {
+    // Stock `order` reserves when written.
+    pub fn reserved(&self, order: &Order) -> i32 {
+        if self.reserve_stock {
+            order.quantity
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Inserted,
+    Updated,
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RowOutcome {
+    pub id: i32,
+    pub outcome: Outcome,
+}
+
+// What happened to every row of an ingested batch, in batch order.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct BatchReport {
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64,
+    pub outcomes: Vec<RowOutcome>,
+}
+
+impl BatchReport {
+    fn record(&mut self, id: i32, outcome: Outcome) {
+        match outcome {
+            Outcome::Inserted => self.inserted += 1,
+            Outcome::Updated => self.updated += 1,
+            Outcome::Skipped => self.skipped += 1,
+        }
+        self.outcomes.push(RowOutcome { id, outcome });
+    }
+
+    // Counts the rows planned as inserts but missing from `written` as
+    // skipped, another batch having written the same ids first.
+    fn skip_unwritten(&mut self, written: &HashSet<i32>) {
+        for row in &mut self.outcomes {
+            if row.outcome == Outcome::Inserted && !written.contains(&row.id) {
+                row.outcome = Outcome::Skipped;
+                self.inserted -= 1;
+                self.skipped += 1;
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RegionTotal {
+    pub region: String,
+    pub total: i64,
+}
+
+impl Columns for RegionTotal {
+    const COLUMNS: &'static [&'static str] = &["region", "total"];
+}
+
+// Region along with its distance from the top of the tree and the quantity
+// ordered in it and every region below it.
+#[derive(Serialize, Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct RegionRollup {
+    pub id: i32,
+    #[serde(rename = "region")]
+    pub name: String,
+    pub parent_id: Option<i32>,
+    pub level: i32,
+    pub total: i64,
+}
+
+impl Columns for RegionRollup {
+    const COLUMNS: &'static [&'static str] = &["id", "region", "parent_id", "level", "total"];
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RegionNode {
+    pub id: i32,
+    pub region: String,
+    pub total: i64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<RegionNode>,
+}
+
+impl Columns for RegionNode {
+    const COLUMNS: &'static [&'static str] = &["id", "region", "total", "children"];
+}
+
+impl RegionNode {
+    // Nests `rollups` under their parents, siblings sorted by name and id.
+    pub fn tree(rollups: Vec<RegionRollup>) -> Vec<RegionNode> {
+        let mut children = HashMap::<Option<i32>, Vec<RegionRollup>>::new();
+        for rollup in rollups {
+            children.entry(rollup.parent_id).or_default().push(rollup);
+        }
+
+        fn nest(
+            parent_id: Option<i32>,
+            children: &mut HashMap<Option<i32>, Vec<RegionRollup>>,
+        ) -> Vec<RegionNode> {
+            let mut regions = children.remove(&parent_id).unwrap_or_default();
+            regions.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+            regions
+                .into_iter()
+                .map(|region| RegionNode {
+                    id: region.id,
+                    children: nest(Some(region.id), children),
+                    region: region.name,
+                    total: region.total,
+                })
+                .collect()
+        }
+
+        nest(None, &mut children)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopList {
+    pub region: String,
+    pub top_gifts: Vec<String>,
+}
+
+impl Columns for TopList {
+    const COLUMNS: &'static [&'static str] = &["region", "top_gifts"];
+}
+
+// Column `OrderStore::analytics` sorts its rows by.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Region,
+    Gift,
+    #[default]
+    Total,
+    Orders,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Asc,
+    #[default]
+    Desc,
+}
+
+// Breakdown of the orders asked of `OrderStore::analytics`. Orders are
+// grouped by the enabled dimensions, or all summed up when none is, after
+// applying every filter that is set.
+#[derive(Debug, Clone)]
+pub struct AnalyticsQuery {
+    pub by_region: bool,
+    pub by_gift: bool,
+    pub region_ids: Option<Vec<i32>>,
+    pub gift_prefix: Option<String>,
+    pub min_quantity: Option<i32>,
+    pub max_quantity: Option<i32>,
+    pub sort: SortKey,
+    pub direction: Direction,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// One group of orders. Only the dimensions grouped by are set.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct AnalyticsRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gift: Option<String>,
+    pub total: i64,
+    pub orders: i64,
+}
+
+// Sums over every group matching the filters, not just the returned page.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AnalyticsTotals {
+    pub groups: i64,
+    pub total: i64,
+    pub orders: i64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Analytics {
+    pub rows: Vec<AnalyticsRow>,
+    pub totals: AnalyticsTotals,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SelectColumn {
+    pub name: String,
+    // Type as the database names it, e.g. INT4 on Postgres or INTEGER on
+    // SQLite.
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SelectOutput {
+    pub columns: Vec<SelectColumn>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    // Whether the statement returned more rows than were asked for.
+    pub truncated: bool,
+}
+
+// Time capsule of day 12, a key stamped with when it was saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capsule {
+    pub key: String,
+    pub saved_at: DateTime<Utc>,
+    // When the capsule is gone, none to keep it for good. The next deadline
+    // of a recurring capsule.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub payload: Option<serde_json::Value>,
+    // Seconds between the deadlines of a capsule that never expires.
+    pub every_secs: Option<i64>,
+    // URL POSTed to once a deadline passes.
+    pub callback: Option<String>,
+}
+
+impl Capsule {
+    // Constraint of the capsule table broken by this capsule, if any.
+    pub fn violation(&self) -> Option<&'static str> {
+        if self.key.is_empty() || self.key.chars().count() > 255 {
+            Some("key must be between 1 and 255 characters long")
+        } else if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= self.saved_at)
+        {
+            Some("a capsule has to expire after it was saved")
+        } else if self.every_secs.is_some_and(|every_secs| every_secs <= 0) {
+            Some("a capsule has to recur after a positive number of seconds")
+        } else if self.every_secs.is_some() && self.expires_at.is_none() {
+            Some("a recurring capsule needs a deadline")
+        } else {
+            None
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.every_secs.is_none() && self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Whether a callback is owed for a deadline passed by `now`.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.callback.is_some() && self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // First deadline after `now` for a recurring capsule, the only deadline
+    // for the others.
+    pub fn deadline(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let expires_at = self.expires_at?;
+        match self.every_secs {
+            Some(every_secs) if expires_at <= now => {
+                let every = every_secs * 1000;
+                let missed = (now - expires_at).num_milliseconds() / every + 1;
+                Some(expires_at + TimeDelta::milliseconds(missed * every))
+            }
+            _ => Some(expires_at),
+        }
+    }
+
+    // Deadline a recurring capsule moves on to once it fired at `now`, later
+    // than the one fired even if `now` does not quite reach it.
+    pub fn next_deadline(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.deadline(now.max(self.expires_at?))
+    }
+}
+
+// Callback owed for a passed capsule deadline, waiting to be delivered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapsuleCallback {
+    pub id: i64,
+    pub key: String,
+    pub url: String,
+    pub deadline: DateTime<Utc>,
+    pub payload: Option<serde_json::Value>,
+    // Deliveries tried so far, counting the one under way.
+    pub attempts: i32,
+}
+
+// Message said in a day 19 chat room.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub room: i32,
+    pub user: String,
+    pub message: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+// Tweets seen in a day 19 chat room by a user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatViews {
+    pub room: i32,
+    pub user: String,
+    pub views: i64,
+}
+
+// Storage of orders, regions and the gift catalog, behind days 13 and 18.
+#[async_trait]
+pub trait OrderStore: Send + Sync {
+    // Round-trips `number` through the backend.
+    async fn ping(&self, number: i32) -> Result<i32, AppError>;
+
+    // Removes every order.
+    async fn reset_orders(&self) -> Result<(), AppError>;
+
+    // Removes every gift and region along with every order.
+    async fn reset_all(&self) -> Result<(), AppError>;
+
+    // Writes a batch of orders in one transaction, resolving ids that are
+    // already stored or repeat as told by `params.on_conflict`. With
+    // `OnConflict::Error` nothing is written if any id conflicts, and the
+    // error lists them all. Orders must have a positive quantity and refer
+    // to a stored region, unless `params.create_regions` lets the batch create
+    // the missing ones. With `params.reserve_stock` the quantities written
+    // are taken out of the stock of their gifts. Replaced orders always give
+    // back what they had reserved. Nothing is written if a gift is missing
+    // from the catalog or out of stock.
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        params: &IngestParams,
+    ) -> Result<BatchReport, AppError>;
+
+    // Writes a batch of regions, like `insert_orders`.
+    async fn insert_regions(
+        &self,
+        regions: &[Region],
+        on_conflict: OnConflict,
+    ) -> Result<BatchReport, AppError>;
+
+    // Sum of the quantities of the orders placed within `range`.
+    async fn total_quantity(&self, range: &TimeRange) -> Result<i64, AppError>;
+
+    // Gifts ordered within the range and region of `query`, most ordered
+    // first. Gifts with the same total share a dense rank and are listed by
+    // name. Returns `query.limit` gifts, followed by those tied with the last
+    // one when `query.ties` says so.
+    async fn popular_gifts(&self, query: &PopularQuery) -> Result<Vec<RankedGift>, AppError>;
+
+    // Total quantity ordered within `range` per `bucket`, oldest first.
+    // Buckets without orders are left out.
+    async fn total_series(
+        &self,
+        range: &TimeRange,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, i64)>, AppError>;
+
+    // Most ordered gift within `range` per `bucket`, oldest first, optionally
+    // only counting the orders of one region. Ties go to the first gift by
+    // name and buckets without orders are left out.
+    async fn popular_series(
+        &self,
+        range: &TimeRange,
+        region_id: Option<i32>,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, String)>, AppError>;
+
+    // Streams every order sorted by id, a page at a time, so the table never
+    // has to fit in memory. Orders written while the stream is read may or
+    // may not show up.
+    async fn export_orders(&self) -> Result<OrderPages, AppError>;
+
+    // Every gift of the catalog, sorted by name.
+    async fn gifts(&self) -> Result<Vec<Gift>, AppError>;
+
+    // The gift named `name`, if the catalog has it.
+    async fn gift(&self, name: &str) -> Result<Option<Gift>, AppError>;
+
+    // Adds `gift` to the catalog, failing if the name is taken.
+    async fn create_gift(&self, gift: &Gift) -> Result<(), AppError>;
+
+    // Adds `gift` to the catalog or overwrites the gift of the same name.
+    // Returns whether it was added.
+    async fn put_gift(&self, gift: &Gift) -> Result<bool, AppError>;
+
+    // Removes the gift named `name` from the catalog, returning whether it
+    // was there. Orders for it are kept.
+    async fn delete_gift(&self, name: &str) -> Result<bool, AppError>;
+
+    // Gifts with a stock at or below `threshold`, or below their own
+    // threshold when `None`. Scarcest first, then by name.
+    async fn low_stock(&self, threshold: Option<i32>) -> Result<Vec<Gift>, AppError>;
+
+    // Total quantity ordered per region name, sorted by name. Orders whose
+    // region does not exist are left out.
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError>;
+
+    // Every region less than `depth` levels below the top of the tree, with
+    // the quantity ordered in it and in every region below it, however deep.
+    async fn region_rollups(&self, depth: i32) -> Result<Vec<RegionRollup>, AppError>;
+
+    // The `limit` most ordered gifts of every region, sorted by region name.
+    async fn top_lists(&self, limit: i64) -> Result<Vec<TopList>, AppError>;
+
+    // One page of the breakdown described by `query`, along with the grand
+    // totals. Ties in the sort key are broken by region name, region id and
+    // gift name.
+    async fn analytics(&self, query: &AnalyticsQuery) -> Result<Analytics, AppError>;
+
+    // Runs `sql`, a single SELECT reading orders, regions and gifts, in a
+    // read-only transaction that is cut short after `PLAYGROUND_TIMEOUT`.
+    // Returns at most `max_rows` rows, values converted to the closest JSON
+    // type. Statements the database rejects are client errors.
+    async fn run_select(&self, sql: &str, max_rows: usize) -> Result<SelectOutput, AppError>;
+}
+
+// Storage of the day 12 time capsules and the callbacks they owe.
+#[async_trait]
+pub trait CapsuleStore: Send + Sync {
+    // Stores `capsule`, replacing the one saved under the same key, and
+    // drops every capsule that expired by the time it was saved.
+    async fn save_capsule(&self, capsule: &Capsule) -> Result<(), AppError>;
+
+    // The capsule saved under `key`, unless it expired by `now`.
+    async fn capsule(&self, key: &str, now: DateTime<Utc>) -> Result<Option<Capsule>, AppError>;
+
+    // Every capsule not expired by `now`, sorted by key.
+    async fn capsules(&self, now: DateTime<Utc>) -> Result<Vec<Capsule>, AppError>;
+
+    // Removes the capsule saved under `key`, returning whether there was one
+    // not expired by `now`.
+    async fn delete_capsule(&self, key: &str, now: DateTime<Utc>) -> Result<bool, AppError>;
+
+    // Queues a callback, due at `now`, for every capsule with a callback
+    // whose deadline passed by `now`. Recurring capsules move on to their
+    // next deadline, the others are removed. Returns the callbacks queued.
+    async fn fire_capsules(&self, now: DateTime<Utc>) -> Result<usize, AppError>;
+
+    // Claims up to `limit` callbacks due by `now`, counting an attempt for
+    // each and putting them off until `lease_until`, so that callbacks whose
+    // delivery is never reported are tried again.
+    async fn claim_callbacks(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<CapsuleCallback>, AppError>;
+
+    // Puts a claimed callback off until `due_at`.
+    async fn retry_callback(&self, id: i64, due_at: DateTime<Utc>) -> Result<(), AppError>;
+
+    // Removes a callback once delivered or given up on.
+    async fn finish_callback(&self, id: i64) -> Result<(), AppError>;
+}
+
+// Storage of the day 19 chat history and view counts.
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+    // Adds a message to the history of `room`, then drops all but the newest
+    // `keep` messages of the room, `keep` being at least 1. Returns the
+    // message with its id, which grows with every message added.
+    async fn append_chat(
+        &self,
+        room: i32,
+        user: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        keep: i64,
+    ) -> Result<ChatMessage, AppError>;
+
+    // The newest `limit` messages of `room` older than message `before`,
+    // oldest first.
+    async fn chat_history(
+        &self,
+        room: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError>;
+
+    // The oldest `limit` messages of `room` newer than message `after`,
+    // oldest first.
+    async fn chat_after(
+        &self,
+        room: i32,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError>;
+
+    // Message `id` of `room`, unless gone from the history.
+    async fn chat_message(&self, room: i32, id: i64) -> Result<Option<ChatMessage>, AppError>;
+
+    // Replaces the text of message `id` of `room`, if still in the history.
+    async fn edit_chat(&self, room: i32, id: i64, message: &str) -> Result<(), AppError>;
+
+    // Removes message `id` from the history of `room`.
+    async fn delete_chat(&self, room: i32, id: i64) -> Result<(), AppError>;
+
+    // Removes the history of every room.
+    async fn reset_chat(&self) -> Result<(), AppError>;
+
+    // Stored view counts of every room and user.
+    async fn chat_views(&self) -> Result<Vec<ChatViews>, AppError>;
+
+    // Adds `views` to the stored counts of their rooms and users.
+    async fn add_chat_views(&self, views: &[ChatViews]) -> Result<(), AppError>;
+
+    // Removes the stored view counts of `room` and `user`, of every room or
+    // user when not given.
+    async fn reset_chat_views(&self, room: Option<i32>, user: Option<&str>)
+        -> Result<(), AppError>;
+}
+
+// Cuts a ranking sorted by rank down to what `query` asks for. Stores may
+// hand over any gift ranked within the limit, since a dense rank never
+// exceeds the position of a gift.
+fn cut_ranking(mut gifts: Vec<RankedGift>, query: &PopularQuery) -> Vec<RankedGift> {
+    let limit = usize::try_from(query.limit).unwrap_or(usize::MAX);
+    let kept = match (
+        query.ties,
+        limit.checked_sub(1).and_then(|last| gifts.get(last)),
+    ) {
+        (Ties::Include, Some(last)) => {
+            let rank = last.rank;
+            gifts.iter().take_while(|gift| gift.rank <= rank).count()
+        }
+        _ => limit,
+    };
+    gifts.truncate(kept);
+    gifts
+}
+
+// Row of a table written in batches.
+trait Row: Clone {
+    // Singular table name, used in error messages.
+    const NAME: &'static str;
+    // Whether rows carry a quantity `OnConflict::AddQuantity` can add up.
+    const HAS_QUANTITY: bool = false;
+
+    fn id(&self) -> i32;
+
+    // Constraint of the table broken by this row, if any.
+    fn violation(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Fills in the columns left out by the client for a row stored at `now`.
+    fn fill_defaults(&mut self, _now: DateTime<Utc>) {}
+
+    // Folds a later row with the same id into this one.
+    fn add_quantity(&mut self, _other: &Self) -> Result<(), AppError> {
+        Ok(())
+    }
+}
+
+impl Row for Order {
+    const NAME: &'static str = "order";
+    const HAS_QUANTITY: bool = true;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn violation(&self) -> Option<&'static str> {
+        (self.quantity <= 0).then_some("quantity must be greater than zero")
+    }
+
+    fn fill_defaults(&mut self, now: DateTime<Utc>) {
+        self.created_at.get_or_insert(now);
+    }
+
+    fn add_quantity(&mut self, other: &Self) -> Result<(), AppError> {
+        self.quantity = self.quantity.checked_add(other.quantity).ok_or_else(|| {
+            AppError::bad_request(format!("quantity of order {} overflows", self.id))
+        })?;
+        Ok(())
+    }
+}
+
+impl Row for Region {
+    const NAME: &'static str = "region";
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn violation(&self) -> Option<&'static str> {
+        (self.parent_id == Some(self.id)).then_some("a region cannot be its own parent")
+    }
+}
+
+// Decides what happens to every row of `batch` given the ids already
+// stored, and folds rows repeating an id into the single row to write for
+// it. Returns the rows to write along with the report for the client.
+fn plan_batch<T: Row>(
+    batch: &[T],
+    existing: &HashSet<i32>,
+    on_conflict: OnConflict,
+) -> Result<(Vec<T>, BatchReport), AppError> {
+    if on_conflict == OnConflict::AddQuantity && !T::HAS_QUANTITY {
+        return Err(AppError::bad_request(format!(
+            "{}s have no quantity, add_quantity only applies to orders",
+            T::NAME
+        )));
+    }
+
+    let invalid = batch
+        .iter()
+        .filter_map(|row| Some((row.id(), row.violation()?)))
+        .collect::<Vec<_>>();
+    if let Some(&(id, violation)) = invalid.first() {
+        let ids = invalid.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        return Err(AppError::bad_request(format!(
+            "nothing was inserted, {} {id} is invalid: {violation}",
+            T::NAME
+        ))
+        .with_extension("invalid_ids", ids));
+    }
+
+    if on_conflict == OnConflict::Error {
+        let fresh = batch
+            .iter()
+            .map(T::id)
+            .filter(|id| !existing.contains(id))
+            .collect::<Vec<_>>();
+        let rejected = rejected_ids(batch.iter().map(T::id), &fresh);
+        if !rejected.is_empty() {
+            return Err(batch_conflict(T::NAME, rejected));
+        }
+    }
+
+    let mut rows = Vec::<T>::new();
+    let mut positions = HashMap::<i32, usize>::new();
+    let mut report = BatchReport::default();
+    for row in batch {
+        let id = row.id();
+        let outcome = match (positions.get(&id), on_conflict) {
+            (None, _) if !existing.contains(&id) => {
+                positions.insert(id, rows.len());
+                rows.push(row.clone());
+                Outcome::Inserted
+            }
+            (_, OnConflict::Error | OnConflict::Skip) => Outcome::Skipped,
+            (None, _) => {
+                positions.insert(id, rows.len());
+                rows.push(row.clone());
+                Outcome::Updated
+            }
+            (Some(&position), OnConflict::Replace) => {
+                rows[position] = row.clone();
+                Outcome::Updated
+            }
+            (Some(&position), OnConflict::AddQuantity) => {
+                rows[position].add_quantity(row)?;
+                Outcome::Updated
+            }
+        };
+        report.record(id, outcome);
+    }
+
+    let now = Utc::now();
+    for row in &mut rows {
+        row.fill_defaults(now);
+    }
+
+    Ok((rows, report))
+}
+
+// Ids of a batch which did not make it into the table, given the ids that
+// did. An id repeated within the batch is only inserted once.
+fn rejected_ids(batch: impl IntoIterator<Item = i32>, inserted: &[i32]) -> Vec<i32> {
+    let mut inserted = inserted.iter().copied().collect::<HashSet<_>>();
+    batch
+        .into_iter()
+        .filter(|id| !inserted.remove(id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// Conflict rejecting a whole batch of `table` rows because of `ids`.
+fn batch_conflict(table: &str, ids: Vec<i32>) -> AppError {
+    AppError::conflict(format!(
+        "nothing was inserted, {table} ids already exist or repeat: {}",
+        id_list(&ids)
+    ))
+    .with_extension("conflicting_ids", ids)
+}
+
+// Fails unless every order refers to one of the `known` regions.
+fn check_regions(orders: &[Order], known: &HashSet<i32>) -> Result<(), AppError> {
+    let unknown = orders
+        .iter()
+        .map(|order| order.region_id)
+        .filter(|id| !known.contains(id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    Err(AppError::conflict(format!(
+        "nothing was inserted, orders refer to unknown regions: {}",
+        id_list(&unknown)
+    ))
+    .with_extension("unknown_region_ids", unknown))
+}
+
+// Regions to create for the orders referring to regions not `known`, each
+// named after its id, sorted by id.
+fn missing_regions(orders: &[Order], known: &HashSet<i32>) -> Vec<Region> {
+    orders
+        .iter()
+        .map(|order| order.region_id)
+        .filter(|id| !known.contains(id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|id| Region {
+            id,
+            name: format!("Region {id}"),
+            parent_id: None,
+        })
+        .collect()
+}
+
+// Fails unless every region of `regions` has a known parent and none ends up
+// below itself, given the `parents` of the regions already stored.
+fn check_parents(regions: &[Region], parents: &HashMap<i32, Option<i32>>) -> Result<(), AppError> {
+    let mut parents = parents.clone();
+    parents.extend(regions.iter().map(|region| (region.id, region.parent_id)));
+
+    let unknown = regions
+        .iter()
+        .filter_map(|region| region.parent_id)
+        .filter(|id| !parents.contains_key(id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(AppError::conflict(format!(
+            "nothing was inserted, regions refer to unknown parents: {}",
+            id_list(&unknown)
+        ))
+        .with_extension("unknown_parent_ids", unknown));
+    }
+
+    // The stored regions form a tree, so any cycle goes through the batch.
+    let cyclic = regions
+        .iter()
+        .map(|region| region.id)
+        .filter(|&id| {
+            let mut ancestor = parents[&id];
+            for _ in 0..parents.len() {
+                match ancestor {
+                    Some(ancestor_id) if ancestor_id == id => return true,
+                    Some(ancestor_id) => ancestor = parents[&ancestor_id],
+                    None => return false,
+                }
+            }
+            false
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if !cyclic.is_empty() {
+        return Err(AppError::conflict(format!(
+            "nothing was inserted, regions would end up below themselves: {}",
+            id_list(&cyclic)
+        ))
+        .with_extension("cyclic_region_ids", cyclic));
+    }
+
+    Ok(())
+}
+
+// Stock each gift gives up when `rows` are written over the `stored` orders
+// with the same ids, given as (gift name, stock reserved). Replaced orders
+// always give back what they reserved, whether or not `params` reserves stock
+// for the new ones, so amounts may be negative. Gifts left even are dropped.
+fn stock_demand(
+    rows: &[Order],
+    stored: &HashMap<i32, (String, i32)>,
+    params: &IngestParams,
+) -> BTreeMap<String, i64> {
+    let mut demand = BTreeMap::<String, i64>::new();
+    for order in rows {
+        let gift = match (params.on_conflict, stored.get(&order.id)) {
+            // Adding up keeps the gift of the stored order.
+            (OnConflict::AddQuantity, Some((gift, _))) => gift,
+            (OnConflict::Replace, Some((gift, reserved))) => {
+                *demand.entry(gift.clone()).or_default() -= *reserved as i64;
+                &order.gift_name
+            }
+            _ => &order.gift_name,
+        };
+        *demand.entry(gift.clone()).or_default() += params.reserved(order) as i64;
+    }
+
+    demand.retain(|_, amount| *amount != 0);
+    demand
+}
+
+// Fails unless the `stock` of the catalog covers every gift `demand` takes
+// from.
+fn check_stock(
+    demand: &BTreeMap<String, i64>,
+    stock: &HashMap<String, i32>,
+) -> Result<(), AppError> {
+    let taken = demand.iter().filter(|(_, amount)| **amount > 0);
+
+    let unknown = taken
+        .clone()
+        .filter(|(gift, _)| !stock.contains_key(*gift))
+        .map(|(gift, _)| gift.as_str())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(AppError::conflict(format!(
+            "nothing was inserted, orders refer to gifts missing from the catalog: {}",
+            unknown.join(", ")
+        ))
+        .with_extension("unknown_gifts", unknown));
+    }
+
+    let short = taken
+        .filter(|(gift, amount)| **amount > stock[*gift] as i64)
+        .collect::<Vec<_>>();
+    if !short.is_empty() {
+        let gifts = short
+            .iter()
+            .map(|(gift, _)| gift.as_str())
+            .collect::<Vec<_>>();
+        let details = short
+            .iter()
+            .map(|(gift, amount)| {
+                serde_json::json!({"gift": gift, "requested": amount, "available": stock[*gift]})
+            })
+            .collect::<Vec<_>>();
+        return Err(AppError::conflict(format!(
+            "nothing was inserted, not enough stock of: {}",
+            gifts.join(", ")
+        ))
+        .with_extension("insufficient_stock", details));
+    }
+
+    Ok(())
+}
+
+fn gift_exists(name: &str) -> AppError {
+    AppError::conflict(format!("gift {name:?} is already in the catalog"))
+}
+
+fn id_list(ids: &[i32]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Opens the store described by `url` and brings its schema up to date.
+//
+// - `memory` keeps everything in process memory
+// - `sqlite:<path>` or `sqlite::memory:` uses SQLite, with the `sqlite`
+//   feature
+// - `postgres://...` uses Postgres
+pub async fn connect(url: &str) -> anyhow::Result<Store> {
+    if url == "memory" {
+        return Ok(Arc::new(MemoryStore::default()));
+    }
+
+    if url.starts_with("sqlite:") {
+        #[cfg(feature = "sqlite")]
+        return Ok(Arc::new(SqliteStore::connect(url).await?));
+        #[cfg(not(feature = "sqlite"))]
+        anyhow::bail!("SQLite support requires the `sqlite` feature");
+    }
+
+    if url.starts_with("postgres:") || url.starts_with("postgresql:") {
+        return Ok(Arc::new(PgStore::connect(url).await?));
+    }
+
+    anyhow::bail!("unsupported store URL {url:?}, expected memory, sqlite: or postgres:")
+}
@@ -0,0 +1,183 @@
+use std::{collections::HashSet, ops::ControlFlow};
+
+use sqlparser::{
+    ast::{Expr, ObjectName, Query, SetExpr, Statement, Visit, Visitor},
+    dialect::Dialect,
+    parser::Parser,
+};
+
+use super::PLAYGROUND_TIMEOUT;
+use crate::calendar::error::AppError;
+
+// Checks behind `OrderStore::run_select`, shared by the Postgres and SQLite
+// stores. The read-only transaction is what keeps the data safe, these
+// checks keep statements away from everything but the order tables.
+
+// Tables a playground statement may read.
+const TABLES: &[&str] = &["gifts", "orders", "regions"];
+
+// Functions a playground statement may call: aggregates, window functions
+// and the usual scalar helpers of both databases. Anything else, such as
+// functions reading files or settings, is rejected.
+const FUNCTIONS: &[&str] = &[
+    "abs",
+    "array_agg",
+    "avg",
+    "ceil",
+    "ceiling",
+    "char_length",
+    "coalesce",
+    "concat",
+    "count",
+    "cume_dist",
+    "current_date",
+    "current_timestamp",
+    "date",
+    "date_part",
+    "date_trunc",
+    "datetime",
+    "dense_rank",
+    "first_value",
+    "floor",
+    "greatest",
+    "group_concat",
+    "ifnull",
+    "julianday",
+    "lag",
+    "last_value",
+    "lead",
+    "least",
+    "length",
+    "lower",
+    "ltrim",
+    "max",
+    "min",
+    "now",
+    "ntile",
+    "nullif",
+    "percent_rank",
+    "rank",
+    "replace",
+    "round",
+    "row_number",
+    "rtrim",
+    "string_agg",
+    "strftime",
+    "substr",
+    "sum",
+    "to_char",
+    "total",
+    "trim",
+    "upper",
+];
+
+// Parses `sql` as a single SELECT in `dialect` and renders it again, so the
+// database runs exactly the statement that was checked.
+pub(super) fn check_select(dialect: &dyn Dialect, sql: &str) -> Result<String, AppError> {
+    let statements = Parser::parse_sql(dialect, sql)
+        .map_err(|err| AppError::bad_request(format!("failed to parse query: {err}")))?;
+    let [statement @ Statement::Query(_)] = statements.as_slice() else {
+        return Err(AppError::bad_request("expected a single SELECT statement"));
+    };
+
+    let mut checker = Checker::default();
+    if let ControlFlow::Break(err) = statement.visit(&mut checker) {
+        return Err(err);
+    }
+
+    Ok(statement.to_string())
+}
+
+// Error for a statement the database rejected or cancelled.
+pub(super) fn query_error(err: sqlx::Error) -> AppError {
+    let sqlx::Error::Database(db_err) = &err else {
+        return err.into();
+    };
+
+    match db_err.code().as_deref() {
+        // query_canceled on Postgres, SQLITE_INTERRUPT on SQLite
+        Some("57014" | "9") => AppError::bad_request(format!(
+            "query ran longer than {} ms",
+            PLAYGROUND_TIMEOUT.as_millis()
+        )),
+        _ => AppError::bad_request(db_err.message()),
+    }
+}
+
+// Text of a numeric value as a JSON number, or as a string for those JSON
+// has no room for, like NaN.
+pub(super) fn json_number(text: &str) -> serde_json::Value {
+    text.parse::<serde_json::Number>()
+        .map_or_else(|_| text.into(), serde_json::Value::Number)
+}
+
+#[derive(Default)]
+struct Checker {
+    // Names of the common table expressions seen so far, which may be read
+    // like tables.
+    ctes: HashSet<String>,
+}
+
+impl Visitor for Checker {
+    type Break = AppError;
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<AppError> {
+        // Data-modifying statements can hide in a query, e.g. in a CTE.
+        match statement {
+            Statement::Query(_) => ControlFlow::Continue(()),
+            _ => ControlFlow::Break(AppError::bad_request("only SELECT statements can be run")),
+        }
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<AppError> {
+        if !query.locks.is_empty() {
+            return ControlFlow::Break(AppError::bad_request("rows cannot be locked"));
+        }
+        if matches!(&*query.body, SetExpr::Select(select) if select.into.is_some()) {
+            return ControlFlow::Break(AppError::bad_request("SELECT INTO cannot be run"));
+        }
+
+        if let Some(with) = &query.with {
+            self.ctes.extend(
+                with.cte_tables
+                    .iter()
+                    .map(|cte| cte.alias.name.value.to_lowercase()),
+            );
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<AppError> {
+        match single_name(relation) {
+            Some(name) if TABLES.contains(&name.as_str()) || self.ctes.contains(&name) => {
+                ControlFlow::Continue(())
+            }
+            _ => ControlFlow::Break(AppError::bad_request(format!(
+                "table {relation} cannot be read, expected one of {}",
+                TABLES.join(", ")
+            ))),
+        }
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<AppError> {
+        let Expr::Function(function) = expr else {
+            return ControlFlow::Continue(());
+        };
+
+        match single_name(&function.name) {
+            Some(name) if FUNCTIONS.contains(&name.as_str()) => ControlFlow::Continue(()),
+            _ => ControlFlow::Break(AppError::bad_request(format!(
+                "function {} cannot be called",
+                function.name
+            ))),
+        }
+    }
+}
+
+// `name` in lower case, unless it is qualified by a schema.
+fn single_name(name: &ObjectName) -> Option<String> {
+    match name.0.as_slice() {
+        [ident] => Some(ident.value.to_lowercase()),
+        _ => None,
+    }
+}
@@ -0,0 +1,1019 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde_json::Value;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlx::{
+    migrate::MigrateError,
+    postgres::{PgPoolOptions, PgRow},
+    Column, Executor, PgPool, Postgres, Row, Statement, TypeInfo, ValueRef,
+};
+
+use super::{
+    analytics, check_parents, check_regions, check_stock, cut_ranking, gift_exists,
+    missing_regions, plan_batch, playground, stock_demand, Analytics, AnalyticsQuery, AnalyticsRow,
+    AnalyticsTotals, BatchReport, Bucket, Capsule, CapsuleCallback, CapsuleStore, ChatMessage,
+    ChatStore, ChatViews, Gift, IngestParams, OnConflict, Order, OrderPages, OrderStore,
+    PopularQuery, RankedGift, Region, RegionRollup, RegionTotal, SelectColumn, SelectOutput,
+    TimeRange, TopList, EXPORT_PAGE_SIZE, PLAYGROUND_TIMEOUT,
+};
+use crate::calendar::error::AppError;
+
+#[derive(Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let store = Self::new(PgPoolOptions::new().connect(url).await?);
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        sqlx::migrate!().run(&self.pool).await
+    }
+}
+
+#[async_trait]
+impl OrderStore for PgStore {
+    async fn ping(&self, number: i32) -> Result<i32, AppError> {
+        let number = sqlx::query!(r#"SELECT $1::INT as "number!""#, number)
+            .fetch_one(&self.pool)
+            .await?
+            .number;
+
+        Ok(number)
+    }
+
+    async fn reset_orders(&self) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("TRUNCATE orders RESTART IDENTITY CASCADE")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reset_all(&self) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("TRUNCATE orders, regions, gifts RESTART IDENTITY CASCADE")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        params: &IngestParams,
+    ) -> Result<BatchReport, AppError> {
+        let on_conflict = params.on_conflict;
+        let ids = orders.iter().map(|order| order.id).collect::<Vec<_>>();
+
+        let mut tx = self.pool.begin().await?;
+        let stored = sqlx::query!(
+            "SELECT id, gift_name, reserved FROM orders WHERE id = ANY($1)",
+            &ids
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|order| (order.id, (order.gift_name, order.reserved)))
+        .collect::<HashMap<_, _>>();
+        let existing = stored.keys().copied().collect();
+        let (mut rows, mut report) = plan_batch(orders, &existing, on_conflict)?;
+
+        let region_ids = rows.iter().map(|order| order.region_id).collect::<Vec<_>>();
+        let mut known =
+            sqlx::query_scalar!("SELECT id FROM regions WHERE id = ANY($1)", &region_ids)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .collect();
+        if params.create_regions {
+            let created = missing_regions(&rows, &known);
+            let ids = created.iter().map(|region| region.id).collect::<Vec<_>>();
+            let names = created
+                .iter()
+                .map(|region| region.name.clone())
+                .collect::<Vec<_>>();
+            // Batches racing to create the same region leave it to the first.
+            sqlx::query!(
+                "INSERT INTO regions (id, name)
+                  SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[])
+                  ON CONFLICT (id) DO NOTHING",
+                &ids,
+                &names,
+            )
+            .execute(&mut *tx)
+            .await?;
+            known.extend(ids);
+        }
+        check_regions(&rows, &known)?;
+
+        let ids = rows.iter().map(|order| order.id).collect::<Vec<_>>();
+        let gift_names = rows
+            .iter()
+            .map(|order| order.gift_name.clone())
+            .collect::<Vec<_>>();
+        let quantities = rows.iter().map(|order| order.quantity).collect::<Vec<_>>();
+        let created_ats = rows
+            .iter()
+            .map(|order| order.created_at.unwrap_or_else(Utc::now))
+            .collect::<Vec<_>>();
+        let reserved = rows
+            .iter()
+            .map(|order| params.reserved(order))
+            .collect::<Vec<_>>();
+
+        // One statement for the whole batch, which holds no repeated ids
+        // anymore.
+        let query = match on_conflict {
+            OnConflict::Error => Some(sqlx::query!(
+                "INSERT INTO orders (id, region_id, gift_name, quantity, created_at, reserved)
+                  SELECT * FROM UNNEST(
+                    $1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[], $6::INT[]
+                  )",
+                &ids,
+                &region_ids,
+                &gift_names,
+                &quantities,
+                &created_ats,
+                &reserved,
+            )),
+            // Ids another batch wrote since they were looked up are skipped
+            // as well, so only what was written takes stock.
+            OnConflict::Skip => {
+                let written = sqlx::query_scalar!(
+                    "INSERT INTO orders (id, region_id, gift_name, quantity, created_at, reserved)
+                      SELECT * FROM UNNEST(
+                        $1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[], $6::INT[]
+                      )
+                      ON CONFLICT (id) DO NOTHING
+                      RETURNING id",
+                    &ids,
+                    &region_ids,
+                    &gift_names,
+                    &quantities,
+                    &created_ats,
+                    &reserved,
+                )
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .collect();
+                report.skip_unwritten(&written);
+                rows.retain(|order| written.contains(&order.id));
+                None
+            }
+            OnConflict::Replace => Some(sqlx::query!(
+                "INSERT INTO orders (id, region_id, gift_name, quantity, created_at, reserved)
+                  SELECT * FROM UNNEST(
+                    $1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[], $6::INT[]
+                  )
+                  ON CONFLICT (id) DO UPDATE SET
+                    region_id = EXCLUDED.region_id,
+                    gift_name = EXCLUDED.gift_name,
+                    quantity = EXCLUDED.quantity,
+                    created_at = EXCLUDED.created_at,
+                    reserved = EXCLUDED.reserved",
+                &ids,
+                &region_ids,
+                &gift_names,
+                &quantities,
+                &created_ats,
+                &reserved,
+            )),
+            OnConflict::AddQuantity => Some(sqlx::query!(
+                "INSERT INTO orders (id, region_id, gift_name, quantity, created_at, reserved)
+                  SELECT * FROM UNNEST(
+                    $1::INT[], $2::INT[], $3::VARCHAR[], $4::INT[], $5::TIMESTAMPTZ[], $6::INT[]
+                  )
+                  ON CONFLICT (id) DO UPDATE SET
+                    quantity = orders.quantity + EXCLUDED.quantity,
+                    reserved = orders.reserved + EXCLUDED.reserved",
+                &ids,
+                &region_ids,
+                &gift_names,
+                &quantities,
+                &created_ats,
+                &reserved,
+            )),
+        };
+        if let Some(query) = query {
+            query.execute(&mut *tx).await?;
+        }
+
+        // Stock is only checked once the batch is written, which the
+        // transaction takes back if there is not enough.
+        let demand = stock_demand(&rows, &stored, params);
+        let gifts = demand.keys().cloned().collect::<Vec<_>>();
+        let amounts = demand.values().copied().collect::<Vec<_>>();
+        if !demand.is_empty() {
+            // Locked in name order so concurrent batches cannot deadlock.
+            let stock = sqlx::query!(
+                "SELECT name, stock FROM gifts WHERE name = ANY($1) ORDER BY name FOR UPDATE",
+                &gifts,
+            )
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|gift| (gift.name, gift.stock))
+            .collect();
+            check_stock(&demand, &stock)?;
+        }
+
+        if !demand.is_empty() {
+            sqlx::query!(
+                "UPDATE gifts SET stock = gifts.stock - demand.amount
+                  FROM UNNEST($1::VARCHAR[], $2::BIGINT[]) AS demand (name, amount)
+                  WHERE gifts.name = demand.name",
+                &gifts,
+                &amounts,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    async fn insert_regions(
+        &self,
+        regions: &[Region],
+        on_conflict: OnConflict,
+    ) -> Result<BatchReport, AppError> {
+        let ids = regions.iter().map(|region| region.id).collect::<Vec<_>>();
+
+        let mut tx = self.pool.begin().await?;
+        let existing = sqlx::query_scalar!("SELECT id FROM regions WHERE id = ANY($1)", &ids)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+        let (rows, report) = plan_batch(regions, &existing, on_conflict)?;
+        let parents = sqlx::query!("SELECT id, parent_id FROM regions")
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|region| (region.id, region.parent_id))
+            .collect();
+        check_parents(&rows, &parents)?;
+
+        let ids = rows.iter().map(|region| region.id).collect::<Vec<_>>();
+        let names = rows
+            .iter()
+            .map(|region| region.name.clone())
+            .collect::<Vec<_>>();
+        let parent_ids = rows
+            .iter()
+            .map(|region| region.parent_id)
+            .collect::<Vec<_>>();
+
+        let query = match on_conflict {
+            OnConflict::Replace => sqlx::query!(
+                "INSERT INTO regions (id, name, parent_id)
+                  SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[], $3::INT[])
+                  ON CONFLICT (id) DO UPDATE SET
+                    name = EXCLUDED.name,
+                    parent_id = EXCLUDED.parent_id",
+                &ids,
+                &names,
+                &parent_ids as &[Option<i32>],
+            ),
+            _ => sqlx::query!(
+                "INSERT INTO regions (id, name, parent_id)
+                  SELECT * FROM UNNEST($1::INT[], $2::VARCHAR[], $3::INT[])",
+                &ids,
+                &names,
+                &parent_ids as &[Option<i32>],
+            ),
+        };
+        query.execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    async fn total_quantity(&self, range: &TimeRange) -> Result<i64, AppError> {
+        let total = sqlx::query!(
+            r#"SELECT COALESCE(SUM(quantity), 0) as "total!" FROM orders
+              WHERE ($1::TIMESTAMPTZ IS NULL OR created_at >= $1)
+                AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)"#,
+            range.from,
+            range.to,
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .total;
+
+        Ok(total)
+    }
+
+    async fn popular_gifts(&self, query: &PopularQuery) -> Result<Vec<RankedGift>, AppError> {
+        let gifts = sqlx::query_as!(
+            RankedGift,
+            r#"SELECT rank as "rank!", gift_name as "gift!", quantity as "quantity!"
+              FROM (
+                SELECT
+                  gift_name,
+                  SUM(quantity) AS quantity,
+                  DENSE_RANK() OVER (ORDER BY SUM(quantity) DESC) AS rank
+                FROM orders
+                WHERE ($1::TIMESTAMPTZ IS NULL OR created_at >= $1)
+                  AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+                  AND ($3::INT IS NULL OR region_id = $3)
+                GROUP BY gift_name
+              ) AS ranked
+              WHERE rank <= $4
+              ORDER BY rank, gift_name"#,
+            query.range.from,
+            query.range.to,
+            query.region_id,
+            query.limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(cut_ranking(gifts, query))
+    }
+
+    async fn total_series(
+        &self,
+        range: &TimeRange,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, i64)>, AppError> {
+        let series = sqlx::query!(
+            r#"SELECT
+                date_trunc($3, created_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' as "start!",
+                SUM(quantity) as "total!"
+              FROM orders
+              WHERE ($1::TIMESTAMPTZ IS NULL OR created_at >= $1)
+                AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+              GROUP BY 1
+              ORDER BY 1"#,
+            range.from,
+            range.to,
+            bucket.as_str(),
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(series
+            .into_iter()
+            .map(|point| (point.start, point.total))
+            .collect())
+    }
+
+    async fn popular_series(
+        &self,
+        range: &TimeRange,
+        region_id: Option<i32>,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, String)>, AppError> {
+        let series = sqlx::query!(
+            r#"SELECT DISTINCT ON (start) start as "start!", gift_name as "popular!"
+              FROM (
+                SELECT
+                  date_trunc($3, created_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS start,
+                  gift_name,
+                  SUM(quantity) AS gift_count
+                FROM orders
+                WHERE ($1::TIMESTAMPTZ IS NULL OR created_at >= $1)
+                  AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+                  AND ($4::INT IS NULL OR region_id = $4)
+                GROUP BY 1, 2
+              ) AS gifts
+              ORDER BY start, gift_count DESC, gift_name"#,
+            range.from,
+            range.to,
+            bucket.as_str(),
+            region_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(series
+            .into_iter()
+            .map(|point| (point.start, point.popular))
+            .collect())
+    }
+
+    async fn export_orders(&self) -> Result<OrderPages, AppError> {
+        // The cursor lives as long as the transaction, which the stream owns
+        // and rolls back if it is dropped halfway.
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DECLARE orders_export NO SCROLL CURSOR FOR
+              SELECT id, region_id, gift_name, quantity, created_at FROM orders ORDER BY id",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let pages = stream::try_unfold(Some(tx), |tx| async move {
+            let Some(mut tx) = tx else {
+                return Ok(None);
+            };
+
+            let page = sqlx::query_as::<_, Order>(&format!(
+                "FETCH FORWARD {EXPORT_PAGE_SIZE} FROM orders_export"
+            ))
+            .fetch_all(&mut *tx)
+            .await?;
+            if page.len() < EXPORT_PAGE_SIZE {
+                tx.commit().await?;
+                return Ok((!page.is_empty()).then_some((page, None)));
+            }
+
+            Ok(Some((page, Some(tx))))
+        });
+
+        Ok(pages.boxed())
+    }
+
+    async fn gifts(&self) -> Result<Vec<Gift>, AppError> {
+        let gifts = sqlx::query_as!(Gift, "SELECT * FROM gifts ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(gifts)
+    }
+
+    async fn gift(&self, name: &str) -> Result<Option<Gift>, AppError> {
+        let gift = sqlx::query_as!(Gift, "SELECT * FROM gifts WHERE name = $1", name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(gift)
+    }
+
+    async fn create_gift(&self, gift: &Gift) -> Result<(), AppError> {
+        let result = sqlx::query!(
+            "INSERT INTO gifts (name, stock, low_stock_threshold) VALUES ($1, $2, $3)
+              ON CONFLICT (name) DO NOTHING",
+            gift.name,
+            gift.stock,
+            gift.low_stock_threshold,
+        )
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(gift_exists(&gift.name));
+        }
+
+        Ok(())
+    }
+
+    async fn put_gift(&self, gift: &Gift) -> Result<bool, AppError> {
+        // xmax is only zero for rows the statement inserted.
+        let created = sqlx::query_scalar!(
+            r#"INSERT INTO gifts (name, stock, low_stock_threshold) VALUES ($1, $2, $3)
+              ON CONFLICT (name) DO UPDATE SET
+                stock = EXCLUDED.stock,
+                low_stock_threshold = EXCLUDED.low_stock_threshold
+              RETURNING (xmax = 0) as "created!""#,
+            gift.name,
+            gift.stock,
+            gift.low_stock_threshold,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(created)
+    }
+
+    async fn delete_gift(&self, name: &str) -> Result<bool, AppError> {
+        let result = sqlx::query!("DELETE FROM gifts WHERE name = $1", name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn low_stock(&self, threshold: Option<i32>) -> Result<Vec<Gift>, AppError> {
+        let gifts = sqlx::query_as!(
+            Gift,
+            "SELECT * FROM gifts
+              WHERE stock <= COALESCE($1, low_stock_threshold)
+              ORDER BY stock, name",
+            threshold,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(gifts)
+    }
+
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError> {
+        let totals = sqlx::query_as!(
+            RegionTotal,
+            r#"SELECT regions.name as "region!", SUM(orders.quantity) as "total!" FROM orders
+              INNER JOIN regions ON orders.region_id=regions.id GROUP BY regions.name ORDER BY regions.name"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(totals)
+    }
+
+    async fn region_rollups(&self, depth: i32) -> Result<Vec<RegionRollup>, AppError> {
+        let rollups = sqlx::query_as!(
+            RegionRollup,
+            r#"WITH RECURSIVE levels AS (
+                SELECT id, 0 AS level FROM regions WHERE parent_id IS NULL
+                UNION ALL
+                SELECT regions.id, levels.level + 1 FROM regions
+                  INNER JOIN levels ON regions.parent_id = levels.id
+                  WHERE levels.level + 1 < $1
+              ), descendants AS (
+                SELECT id AS ancestor_id, id FROM levels
+                UNION ALL
+                SELECT descendants.ancestor_id, regions.id FROM regions
+                  INNER JOIN descendants ON regions.parent_id = descendants.id
+              )
+              SELECT
+                regions.id,
+                regions.name,
+                regions.parent_id,
+                levels.level as "level!",
+                COALESCE(SUM(orders.quantity), 0) as "total!"
+              FROM regions
+              INNER JOIN levels ON levels.id = regions.id
+              INNER JOIN descendants ON descendants.ancestor_id = regions.id
+              LEFT JOIN orders ON orders.region_id = descendants.id
+              GROUP BY regions.id, levels.level
+              ORDER BY levels.level, regions.name, regions.id"#,
+            depth,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rollups)
+    }
+
+    async fn top_lists(&self, limit: i64) -> Result<Vec<TopList>, AppError> {
+        let top_lists = sqlx::query_as!(
+            TopList,
+            r#"select r.name as "region!", COALESCE(NULLIF(ARRAY_AGG(o.gift_name), '{NULL}'), '{}'::text[]) AS "top_gifts!" from regions r left join LATERAL
+              (
+                  select gift_name, sum(quantity) as sum_quantity from orders where r.id = region_id group by gift_name, region_id order by sum_quantity desc limit $1
+              ) o on true group by r.name order by r.name"#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(top_lists)
+    }
+
+    async fn analytics(&self, query: &AnalyticsQuery) -> Result<Analytics, AppError> {
+        let rows = analytics::page::<Postgres>(query)
+            .build_query_as::<AnalyticsRow>()
+            .fetch_all(&self.pool)
+            .await?;
+        let (groups, total, orders) = analytics::totals::<Postgres>(query)
+            .build_query_as::<(i64, i64, i64)>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Analytics {
+            rows,
+            totals: AnalyticsTotals {
+                groups,
+                total,
+                orders,
+            },
+        })
+    }
+
+    async fn run_select(&self, sql: &str, max_rows: usize) -> Result<SelectOutput, AppError> {
+        let sql = playground::check_select(&PostgreSqlDialect {}, sql)?;
+
+        // The settings only last as long as the transaction, which is rolled
+        // back however the statement ends.
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!(
+            "SET LOCAL statement_timeout = {}",
+            PLAYGROUND_TIMEOUT.as_millis()
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        let statement = (&mut *tx)
+            .prepare(&sql)
+            .await
+            .map_err(playground::query_error)?;
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|column| SelectColumn {
+                name: column.name().to_string(),
+                type_name: column.type_info().name().to_string(),
+            })
+            .collect();
+
+        // Without arguments the statement goes through the simple query
+        // protocol, which sends every value as text.
+        let mut rows = (&mut *tx)
+            .fetch(sql.as_str())
+            .take(max_rows.saturating_add(1))
+            .map_err(playground::query_error)
+            .and_then(|row| async move { playground_row(&row) })
+            .try_collect::<Vec<_>>()
+            .await?;
+        let truncated = rows.len() > max_rows;
+        rows.truncate(max_rows);
+
+        Ok(SelectOutput {
+            columns,
+            rows,
+            truncated,
+        })
+    }
+}
+
+#[async_trait]
+impl CapsuleStore for PgStore {
+    async fn save_capsule(&self, capsule: &Capsule) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        // Capsules owing a callback stay until it is queued.
+        sqlx::query!(
+            "DELETE FROM capsules
+              WHERE expires_at <= $1 AND every_secs IS NULL AND callback IS NULL",
+            capsule.saved_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO capsules (key, saved_at, expires_at, payload, every_secs, callback)
+              VALUES ($1, $2, $3, $4, $5, $6)
+              ON CONFLICT (key) DO UPDATE SET
+                saved_at = EXCLUDED.saved_at,
+                expires_at = EXCLUDED.expires_at,
+                payload = EXCLUDED.payload,
+                every_secs = EXCLUDED.every_secs,
+                callback = EXCLUDED.callback",
+            capsule.key,
+            capsule.saved_at,
+            capsule.expires_at,
+            capsule.payload.as_ref(),
+            capsule.every_secs,
+            capsule.callback,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn capsule(&self, key: &str, now: DateTime<Utc>) -> Result<Option<Capsule>, AppError> {
+        let capsule = sqlx::query_as!(
+            Capsule,
+            "SELECT key, saved_at, expires_at, payload, every_secs, callback FROM capsules
+              WHERE key = $1
+                AND (expires_at IS NULL OR expires_at > $2 OR every_secs IS NOT NULL)",
+            key,
+            now,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(capsule)
+    }
+
+    async fn capsules(&self, now: DateTime<Utc>) -> Result<Vec<Capsule>, AppError> {
+        let capsules = sqlx::query_as!(
+            Capsule,
+            "SELECT key, saved_at, expires_at, payload, every_secs, callback FROM capsules
+              WHERE expires_at IS NULL OR expires_at > $1 OR every_secs IS NOT NULL
+              ORDER BY key",
+            now,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn delete_capsule(&self, key: &str, now: DateTime<Utc>) -> Result<bool, AppError> {
+        let result = sqlx::query!(
+            "DELETE FROM capsules
+              WHERE key = $1
+                AND (expires_at IS NULL OR expires_at > $2 OR every_secs IS NOT NULL)",
+            key,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn fire_capsules(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let due = sqlx::query_as!(
+            Capsule,
+            "SELECT key, saved_at, expires_at, payload, every_secs, callback FROM capsules
+              WHERE callback IS NOT NULL AND expires_at <= $1
+              FOR UPDATE SKIP LOCKED",
+            now,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for capsule in &due {
+            sqlx::query!(
+                "INSERT INTO capsule_callbacks (key, url, deadline, payload, due_at)
+                  VALUES ($1, $2, $3, $4, $5)",
+                capsule.key,
+                capsule.callback,
+                capsule.expires_at,
+                capsule.payload.as_ref(),
+                now,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            if capsule.every_secs.is_some() {
+                sqlx::query!(
+                    "UPDATE capsules SET expires_at = $2 WHERE key = $1",
+                    capsule.key,
+                    capsule.next_deadline(now),
+                )
+                .execute(&mut *tx)
+                .await?;
+            } else {
+                sqlx::query!("DELETE FROM capsules WHERE key = $1", capsule.key)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(due.len())
+    }
+
+    async fn claim_callbacks(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<CapsuleCallback>, AppError> {
+        let callbacks = sqlx::query_as!(
+            CapsuleCallback,
+            "UPDATE capsule_callbacks SET attempts = attempts + 1, due_at = $2
+              WHERE id IN (
+                SELECT id FROM capsule_callbacks
+                  WHERE due_at <= $1
+                  ORDER BY due_at
+                  LIMIT $3
+                  FOR UPDATE SKIP LOCKED
+              )
+              RETURNING id, key, url, deadline, payload, attempts",
+            now,
+            lease_until,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(callbacks)
+    }
+
+    async fn retry_callback(&self, id: i64, due_at: DateTime<Utc>) -> Result<(), AppError> {
+        sqlx::query!(
+            "UPDATE capsule_callbacks SET due_at = $2 WHERE id = $1",
+            id,
+            due_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn finish_callback(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query!("DELETE FROM capsule_callbacks WHERE id = $1", id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatStore for PgStore {
+    async fn append_chat(
+        &self,
+        room: i32,
+        user: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        keep: i64,
+    ) -> Result<ChatMessage, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let message = sqlx::query_as!(
+            ChatMessage,
+            r#"INSERT INTO chat_messages (room, user_name, message, sent_at) VALUES ($1, $2, $3, $4)
+              RETURNING id, room, user_name AS "user", message, sent_at"#,
+            room,
+            user,
+            message,
+            sent_at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM chat_messages
+              WHERE room = $1 AND id <= (
+                SELECT id FROM chat_messages WHERE room = $1 ORDER BY id DESC OFFSET $2 LIMIT 1
+              )",
+            room,
+            keep,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    async fn chat_history(
+        &self,
+        room: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let mut messages = sqlx::query_as!(
+            ChatMessage,
+            r#"SELECT id, room, user_name AS "user", message, sent_at FROM chat_messages
+              WHERE room = $1 AND ($2::BIGINT IS NULL OR id < $2)
+              ORDER BY id DESC
+              LIMIT $3"#,
+            room,
+            before,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    async fn chat_after(
+        &self,
+        room: i32,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let messages = sqlx::query_as!(
+            ChatMessage,
+            r#"SELECT id, room, user_name AS "user", message, sent_at FROM chat_messages
+              WHERE room = $1 AND id > $2
+              ORDER BY id
+              LIMIT $3"#,
+            room,
+            after,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    async fn chat_message(&self, room: i32, id: i64) -> Result<Option<ChatMessage>, AppError> {
+        let message = sqlx::query_as!(
+            ChatMessage,
+            r#"SELECT id, room, user_name AS "user", message, sent_at FROM chat_messages
+              WHERE room = $1 AND id = $2"#,
+            room,
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    async fn edit_chat(&self, room: i32, id: i64, message: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            "UPDATE chat_messages SET message = $3 WHERE room = $1 AND id = $2",
+            room,
+            id,
+            message,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_chat(&self, room: i32, id: i64) -> Result<(), AppError> {
+        sqlx::query!(
+            "DELETE FROM chat_messages WHERE room = $1 AND id = $2",
+            room,
+            id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn reset_chat(&self) -> Result<(), AppError> {
+        sqlx::query!("DELETE FROM chat_messages")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn chat_views(&self) -> Result<Vec<ChatViews>, AppError> {
+        let views = sqlx::query_as!(
+            ChatViews,
+            r#"SELECT room, user_name AS "user", views FROM chat_views"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(views)
+    }
+
+    async fn add_chat_views(&self, views: &[ChatViews]) -> Result<(), AppError> {
+        let rooms = views.iter().map(|views| views.room).collect::<Vec<_>>();
+        let users = views
+            .iter()
+            .map(|views| views.user.clone())
+            .collect::<Vec<_>>();
+        let counts = views.iter().map(|views| views.views).collect::<Vec<_>>();
+        sqlx::query!(
+            "INSERT INTO chat_views (room, user_name, views)
+              SELECT * FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::BIGINT[])
+              ON CONFLICT (room, user_name) DO UPDATE SET views = chat_views.views + EXCLUDED.views",
+            &rooms,
+            &users,
+            &counts,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn reset_chat_views(
+        &self,
+        room: Option<i32>,
+        user: Option<&str>,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            "DELETE FROM chat_views
+              WHERE ($1::INTEGER IS NULL OR room = $1) AND ($2::TEXT IS NULL OR user_name = $2)",
+            room,
+            user,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Values of a row fetched as text, converted to the closest JSON type.
+fn playground_row(row: &PgRow) -> Result<Vec<Value>, AppError> {
+    (0..row.len())
+        .map(|index| {
+            let value = row.try_get_raw(index)?;
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+
+            let text = value.as_str().map_err(|err| anyhow::anyhow!(err))?;
+            Ok(match value.type_info().name() {
+                "BOOL" => Value::Bool(text == "t"),
+                "INT2" | "INT4" | "INT8" | "OID" | "FLOAT4" | "FLOAT8" | "NUMERIC" => {
+                    playground::json_number(text)
+                }
+                "JSON" | "JSONB" => serde_json::from_str(text)?,
+                _ => text.into(),
+            })
+        })
+        .collect()
+}
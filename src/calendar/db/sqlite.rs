@@ -0,0 +1,1125 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde_json::Value;
+use sqlparser::dialect::SQLiteDialect;
+use sqlx::{
+    migrate::MigrateError,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    types::Json,
+    Column, Connection, Executor, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool,
+    Statement, TypeInfo, ValueRef,
+};
+use tracing::error;
+
+use super::{
+    analytics, check_parents, check_regions, check_stock, cut_ranking, gift_exists,
+    missing_regions, plan_batch, playground, stock_demand, Analytics, AnalyticsQuery, AnalyticsRow,
+    AnalyticsTotals, BatchReport, Bucket, Capsule, CapsuleCallback, CapsuleStore, ChatMessage,
+    ChatStore, ChatViews, Gift, IngestParams, OnConflict, Order, OrderPages, OrderStore,
+    PopularQuery, RankedGift, Region, RegionRollup, RegionTotal, SelectColumn, SelectOutput,
+    TimeRange, TopList, EXPORT_PAGE_SIZE, PLAYGROUND_TIMEOUT,
+};
+use crate::calendar::error::AppError;
+
+// Rows per INSERT statement, keeping well below SQLite's limit on the number
+// of bound parameters.
+const BATCH_SIZE: usize = 1000;
+
+// Orders placed within the range bound as ?1 and ?2. Timestamps are stored as
+// text in more than one layout, so they are compared through julianday().
+const WITHIN_RANGE: &str = "(?1 IS NULL OR julianday(created_at) >= julianday(?1))
+    AND (?2 IS NULL OR julianday(created_at) < julianday(?2))";
+
+// Capsules that have not expired by the time bound as ?1.
+const LIVE_CAPSULE: &str = "(expires_at IS NULL OR julianday(expires_at) > julianday(?1)
+  OR every_secs IS NOT NULL)";
+
+const CAPSULE_COLUMNS: &str = "key, saved_at, expires_at, payload, every_secs, callback";
+
+// Order store backed by SQLite. Queries are checked at runtime since the
+// compile-time checked macros are bound to the Postgres `DATABASE_URL`.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+
+        // Every connection to an in-memory database gets a database of its
+        // own, so keep a single connection alive for the lifetime of the pool.
+        let pool = if url.contains(":memory:") || url.contains("mode=memory") {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new()
+        }
+        .connect_with(options)
+        .await?;
+
+        let store = Self::new(pool);
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        sqlx::migrate!("./migrations/sqlite").run(&self.pool).await
+    }
+}
+
+// Ids of `batch` already present in `table`.
+async fn existing_ids(
+    conn: &mut SqliteConnection,
+    table: &str,
+    batch: impl Iterator<Item = i32>,
+) -> Result<HashSet<i32>, AppError> {
+    let batch = batch.collect::<Vec<_>>();
+    let mut existing = HashSet::new();
+
+    for chunk in batch.chunks(BATCH_SIZE) {
+        let mut query =
+            QueryBuilder::<Sqlite>::new(format!("SELECT id FROM {table} WHERE id IN ("));
+        let mut ids = query.separated(", ");
+        for id in chunk {
+            ids.push_bind(*id);
+        }
+        query.push(")");
+
+        existing.extend(
+            query
+                .build_query_scalar::<i32>()
+                .fetch_all(&mut *conn)
+                .await?,
+        );
+    }
+
+    Ok(existing)
+}
+
+// Gift name and stock reserved of the orders of `batch` already stored.
+async fn stored_orders(
+    conn: &mut SqliteConnection,
+    batch: impl Iterator<Item = i32>,
+) -> Result<HashMap<i32, (String, i32)>, AppError> {
+    let batch = batch.collect::<Vec<_>>();
+    let mut stored = HashMap::new();
+
+    for chunk in batch.chunks(BATCH_SIZE) {
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT id, gift_name, reserved FROM orders WHERE id IN (");
+        let mut ids = query.separated(", ");
+        for id in chunk {
+            ids.push_bind(*id);
+        }
+        query.push(")");
+
+        stored.extend(
+            query
+                .build_query_as::<(i32, String, i32)>()
+                .fetch_all(&mut *conn)
+                .await?
+                .into_iter()
+                .map(|(id, gift, reserved)| (id, (gift, reserved))),
+        );
+    }
+
+    Ok(stored)
+}
+
+// Stock of the gifts of `names` the catalog has.
+async fn gift_stock(
+    conn: &mut SqliteConnection,
+    names: impl Iterator<Item = &String>,
+) -> Result<HashMap<String, i32>, AppError> {
+    let names = names.collect::<Vec<_>>();
+    let mut stock = HashMap::new();
+
+    for chunk in names.chunks(BATCH_SIZE) {
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT name, stock FROM gifts WHERE name IN (");
+        let mut binds = query.separated(", ");
+        for name in chunk {
+            binds.push_bind(*name);
+        }
+        query.push(")");
+
+        stock.extend(
+            query
+                .build_query_as::<(String, i32)>()
+                .fetch_all(&mut *conn)
+                .await?,
+        );
+    }
+
+    Ok(stock)
+}
+
+#[async_trait]
+impl OrderStore for SqliteStore {
+    async fn ping(&self, number: i32) -> Result<i32, AppError> {
+        let number = sqlx::query_scalar("SELECT ?")
+            .bind(number)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(number)
+    }
+
+    async fn reset_orders(&self) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM orders")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn reset_all(&self) -> Result<(), AppError> {
+        // SQLite has no TRUNCATE, and orders have to go before the regions
+        // they refer to.
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM orders").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM regions").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM gifts").execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: &[Order],
+        params: &IngestParams,
+    ) -> Result<BatchReport, AppError> {
+        let on_conflict = params.on_conflict;
+        let mut tx = self.pool.begin().await?;
+        let stored = stored_orders(&mut tx, orders.iter().map(|order| order.id)).await?;
+        let existing = stored.keys().copied().collect();
+        let (rows, report) = plan_batch(orders, &existing, on_conflict)?;
+        let mut known =
+            existing_ids(&mut tx, "regions", rows.iter().map(|order| order.region_id)).await?;
+        if params.create_regions {
+            let created = missing_regions(&rows, &known);
+            for chunk in created.chunks(BATCH_SIZE) {
+                let mut query = QueryBuilder::<Sqlite>::new("INSERT INTO regions (id, name) ");
+                query.push_values(chunk, |mut row, region| {
+                    row.push_bind(region.id).push_bind(&region.name);
+                });
+                query.build().execute(&mut *tx).await?;
+            }
+            known.extend(created.iter().map(|region| region.id));
+        }
+        check_regions(&rows, &known)?;
+
+        let demand = stock_demand(&rows, &stored, params);
+        if !demand.is_empty() {
+            let stock = gift_stock(&mut tx, demand.keys()).await?;
+            check_stock(&demand, &stock)?;
+        }
+
+        for chunk in rows.chunks(BATCH_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO orders (id, region_id, gift_name, quantity, created_at, reserved) ",
+            );
+            query.push_values(chunk, |mut row, order| {
+                row.push_bind(order.id)
+                    .push_bind(order.region_id)
+                    .push_bind(&order.gift_name)
+                    .push_bind(order.quantity)
+                    .push_bind(order.created_at.unwrap_or_else(Utc::now))
+                    .push_bind(params.reserved(order));
+            });
+            match on_conflict {
+                OnConflict::Error | OnConflict::Skip => {}
+                OnConflict::Replace => {
+                    query.push(
+                        " ON CONFLICT (id) DO UPDATE SET
+                          region_id = excluded.region_id,
+                          gift_name = excluded.gift_name,
+                          quantity = excluded.quantity,
+                          created_at = excluded.created_at,
+                          reserved = excluded.reserved",
+                    );
+                }
+                OnConflict::AddQuantity => {
+                    query.push(
+                        " ON CONFLICT (id) DO UPDATE SET
+                          quantity = orders.quantity + excluded.quantity,
+                          reserved = orders.reserved + excluded.reserved",
+                    );
+                }
+            }
+
+            query.build().execute(&mut *tx).await?;
+        }
+
+        for (gift, amount) in &demand {
+            sqlx::query("UPDATE gifts SET stock = stock - ? WHERE name = ?")
+                .bind(amount)
+                .bind(gift)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    async fn insert_regions(
+        &self,
+        regions: &[Region],
+        on_conflict: OnConflict,
+    ) -> Result<BatchReport, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let existing =
+            existing_ids(&mut tx, "regions", regions.iter().map(|region| region.id)).await?;
+        let (rows, report) = plan_batch(regions, &existing, on_conflict)?;
+        let parents = sqlx::query_as::<_, (i32, Option<i32>)>("SELECT id, parent_id FROM regions")
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+        check_parents(&rows, &parents)?;
+
+        // A region may come in a later statement than the regions below it.
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *tx)
+            .await?;
+        for chunk in rows.chunks(BATCH_SIZE) {
+            let mut query =
+                QueryBuilder::<Sqlite>::new("INSERT INTO regions (id, name, parent_id) ");
+            query.push_values(chunk, |mut row, region| {
+                row.push_bind(region.id)
+                    .push_bind(&region.name)
+                    .push_bind(region.parent_id);
+            });
+            if on_conflict == OnConflict::Replace {
+                query.push(
+                    " ON CONFLICT (id) DO UPDATE SET
+                      name = excluded.name,
+                      parent_id = excluded.parent_id",
+                );
+            }
+
+            query.build().execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    async fn total_quantity(&self, range: &TimeRange) -> Result<i64, AppError> {
+        let total = sqlx::query_scalar(&format!(
+            "SELECT COALESCE(SUM(quantity), 0) FROM orders WHERE {WITHIN_RANGE}"
+        ))
+        .bind(range.from)
+        .bind(range.to)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(total)
+    }
+
+    async fn popular_gifts(&self, query: &PopularQuery) -> Result<Vec<RankedGift>, AppError> {
+        let gifts = sqlx::query_as(&format!(
+            "SELECT rank, gift_name AS gift, quantity FROM (
+                  SELECT gift_name, SUM(quantity) AS quantity,
+                    DENSE_RANK() OVER (ORDER BY SUM(quantity) DESC) AS rank
+                  FROM orders
+                  WHERE {WITHIN_RANGE} AND (?3 IS NULL OR region_id = ?3)
+                  GROUP BY gift_name
+              )
+              WHERE rank <= ?4
+              ORDER BY rank, gift_name"
+        ))
+        .bind(query.range.from)
+        .bind(query.range.to)
+        .bind(query.region_id)
+        .bind(query.limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(cut_ranking(gifts, query))
+    }
+
+    async fn total_series(
+        &self,
+        range: &TimeRange,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, i64)>, AppError> {
+        let series = sqlx::query_as(&format!(
+            "SELECT {} AS start, SUM(quantity) FROM orders
+              WHERE {WITHIN_RANGE}
+              GROUP BY start ORDER BY start",
+            bucket_start(bucket)
+        ))
+        .bind(range.from)
+        .bind(range.to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(series)
+    }
+
+    async fn popular_series(
+        &self,
+        range: &TimeRange,
+        region_id: Option<i32>,
+        bucket: Bucket,
+    ) -> Result<Vec<(DateTime<Utc>, String)>, AppError> {
+        let series = sqlx::query_as(&format!(
+            "SELECT start, gift_name FROM (
+                  SELECT start, gift_name,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY start ORDER BY SUM(quantity) DESC, gift_name
+                    ) AS position
+                  FROM (SELECT {} AS start, gift_name, quantity FROM orders
+                    WHERE {WITHIN_RANGE} AND (?3 IS NULL OR region_id = ?3))
+                  GROUP BY start, gift_name
+              )
+              WHERE position = 1
+              ORDER BY start",
+            bucket_start(bucket)
+        ))
+        .bind(range.from)
+        .bind(range.to)
+        .bind(region_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(series)
+    }
+
+    async fn export_orders(&self) -> Result<OrderPages, AppError> {
+        // SQLite has no cursors outliving a statement, so orders are paged
+        // through by id instead.
+        let pool = self.pool.clone();
+        let pages = stream::try_unfold(Some(None), move |after: Option<Option<i32>>| {
+            let pool = pool.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok(None);
+                };
+
+                let page = sqlx::query_as::<_, Order>(
+                    "SELECT id, region_id, gift_name, quantity, created_at FROM orders
+                      WHERE ?1 IS NULL OR id > ?1
+                      ORDER BY id
+                      LIMIT ?2",
+                )
+                .bind(after)
+                .bind(EXPORT_PAGE_SIZE as i64)
+                .fetch_all(&pool)
+                .await?;
+                let Some(last) = page.last().map(|order| order.id) else {
+                    return Ok(None);
+                };
+
+                let after = (page.len() == EXPORT_PAGE_SIZE).then_some(Some(last));
+                Ok::<_, AppError>(Some((page, after)))
+            }
+        });
+
+        Ok(pages.boxed())
+    }
+
+    async fn gifts(&self) -> Result<Vec<Gift>, AppError> {
+        let gifts = sqlx::query_as("SELECT * FROM gifts ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(gifts)
+    }
+
+    async fn gift(&self, name: &str) -> Result<Option<Gift>, AppError> {
+        let gift = sqlx::query_as("SELECT * FROM gifts WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(gift)
+    }
+
+    async fn create_gift(&self, gift: &Gift) -> Result<(), AppError> {
+        let result = sqlx::query(
+            "INSERT INTO gifts (name, stock, low_stock_threshold) VALUES (?, ?, ?)
+              ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(&gift.name)
+        .bind(gift.stock)
+        .bind(gift.low_stock_threshold)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(gift_exists(&gift.name));
+        }
+
+        Ok(())
+    }
+
+    async fn put_gift(&self, gift: &Gift) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let created = sqlx::query("SELECT 1 FROM gifts WHERE name = ?")
+            .bind(&gift.name)
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_none();
+        sqlx::query(
+            "INSERT INTO gifts (name, stock, low_stock_threshold) VALUES (?, ?, ?)
+              ON CONFLICT (name) DO UPDATE SET
+                stock = excluded.stock,
+                low_stock_threshold = excluded.low_stock_threshold",
+        )
+        .bind(&gift.name)
+        .bind(gift.stock)
+        .bind(gift.low_stock_threshold)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(created)
+    }
+
+    async fn delete_gift(&self, name: &str) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM gifts WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn low_stock(&self, threshold: Option<i32>) -> Result<Vec<Gift>, AppError> {
+        let gifts = sqlx::query_as(
+            "SELECT * FROM gifts
+              WHERE stock <= COALESCE(?, low_stock_threshold)
+              ORDER BY stock, name",
+        )
+        .bind(threshold)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(gifts)
+    }
+
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError> {
+        let totals = sqlx::query_as::<_, (String, i64)>(
+            "SELECT regions.name, SUM(orders.quantity) FROM orders
+              INNER JOIN regions ON orders.region_id = regions.id
+              GROUP BY regions.name ORDER BY regions.name",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(totals
+            .into_iter()
+            .map(|(region, total)| RegionTotal { region, total })
+            .collect())
+    }
+
+    async fn region_rollups(&self, depth: i32) -> Result<Vec<RegionRollup>, AppError> {
+        let rollups = sqlx::query_as(
+            "WITH RECURSIVE levels AS (
+                SELECT id, 0 AS level FROM regions WHERE parent_id IS NULL
+                UNION ALL
+                SELECT regions.id, levels.level + 1 FROM regions
+                  INNER JOIN levels ON regions.parent_id = levels.id
+                  WHERE levels.level + 1 < ?
+              ), descendants AS (
+                SELECT id AS ancestor_id, id FROM levels
+                UNION ALL
+                SELECT descendants.ancestor_id, regions.id FROM regions
+                  INNER JOIN descendants ON regions.parent_id = descendants.id
+              )
+              SELECT
+                regions.id,
+                regions.name,
+                regions.parent_id,
+                levels.level,
+                COALESCE(SUM(orders.quantity), 0) AS total
+              FROM regions
+              INNER JOIN levels ON levels.id = regions.id
+              INNER JOIN descendants ON descendants.ancestor_id = regions.id
+              LEFT JOIN orders ON orders.region_id = descendants.id
+              GROUP BY regions.id, levels.level
+              ORDER BY levels.level, regions.name, regions.id",
+        )
+        .bind(depth)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rollups)
+    }
+
+    async fn top_lists(&self, limit: i64) -> Result<Vec<TopList>, AppError> {
+        // SQLite has no arrays, so fetch one row per (region, gift) and
+        // fold them into lists here. Regions without orders yield a NULL gift.
+        let rows = sqlx::query_as::<_, (String, Option<String>)>(
+            "SELECT r.name, ranked.gift_name FROM regions r
+              LEFT JOIN (
+                  SELECT region_id, gift_name,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY region_id ORDER BY SUM(quantity) DESC, gift_name
+                    ) AS position
+                  FROM orders GROUP BY region_id, gift_name
+              ) ranked ON ranked.region_id = r.id AND ranked.position <= ?
+              ORDER BY r.name, r.id, ranked.position",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut top_lists = Vec::<TopList>::new();
+        for (region, gift) in rows {
+            let top_list = match top_lists.last_mut() {
+                Some(top_list) if top_list.region == region => top_list,
+                _ => {
+                    top_lists.push(TopList {
+                        region,
+                        top_gifts: vec![],
+                    });
+                    top_lists.last_mut().unwrap()
+                }
+            };
+            top_list.top_gifts.extend(gift);
+        }
+
+        Ok(top_lists)
+    }
+
+    async fn analytics(&self, query: &AnalyticsQuery) -> Result<Analytics, AppError> {
+        let rows = analytics::page::<Sqlite>(query)
+            .build_query_as::<AnalyticsRow>()
+            .fetch_all(&self.pool)
+            .await?;
+        let (groups, total, orders) = analytics::totals::<Sqlite>(query)
+            .build_query_as::<(i64, i64, i64)>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Analytics {
+            rows,
+            totals: AnalyticsTotals {
+                groups,
+                total,
+                orders,
+            },
+        })
+    }
+
+    async fn run_select(&self, sql: &str, max_rows: usize) -> Result<SelectOutput, AppError> {
+        let sql = playground::check_select(&SQLiteDialect {}, sql)?;
+
+        // The connection would go back to the pool read-only if the request
+        // were dropped halfway, so the statement is seen through on its own.
+        let pool = self.pool.clone();
+        tokio::spawn(async move { select_read_only(&pool, &sql, max_rows).await }).await?
+    }
+}
+
+#[async_trait]
+impl CapsuleStore for SqliteStore {
+    async fn save_capsule(&self, capsule: &Capsule) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        // Capsules owing a callback stay until it is queued.
+        sqlx::query(
+            "DELETE FROM capsules
+              WHERE julianday(expires_at) <= julianday(?)
+                AND every_secs IS NULL AND callback IS NULL",
+        )
+        .bind(capsule.saved_at)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO capsules (key, saved_at, expires_at, payload, every_secs, callback)
+              VALUES (?, ?, ?, ?, ?, ?)
+              ON CONFLICT (key) DO UPDATE SET
+                saved_at = excluded.saved_at,
+                expires_at = excluded.expires_at,
+                payload = excluded.payload,
+                every_secs = excluded.every_secs,
+                callback = excluded.callback",
+        )
+        .bind(&capsule.key)
+        .bind(capsule.saved_at)
+        .bind(capsule.expires_at)
+        .bind(capsule.payload.as_ref().map(Json))
+        .bind(capsule.every_secs)
+        .bind(&capsule.callback)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn capsule(&self, key: &str, now: DateTime<Utc>) -> Result<Option<Capsule>, AppError> {
+        let capsule = sqlx::query_as::<_, CapsuleRow>(&format!(
+            "SELECT {CAPSULE_COLUMNS} FROM capsules WHERE key = ?2 AND {LIVE_CAPSULE}"
+        ))
+        .bind(now)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(capsule.map(Capsule::from))
+    }
+
+    async fn capsules(&self, now: DateTime<Utc>) -> Result<Vec<Capsule>, AppError> {
+        let capsules = sqlx::query_as::<_, CapsuleRow>(&format!(
+            "SELECT {CAPSULE_COLUMNS} FROM capsules WHERE {LIVE_CAPSULE} ORDER BY key"
+        ))
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules.into_iter().map(Capsule::from).collect())
+    }
+
+    async fn delete_capsule(&self, key: &str, now: DateTime<Utc>) -> Result<bool, AppError> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM capsules WHERE key = ?2 AND {LIVE_CAPSULE}"
+        ))
+        .bind(now)
+        .bind(key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn fire_capsules(&self, now: DateTime<Utc>) -> Result<usize, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let due = sqlx::query_as::<_, CapsuleRow>(&format!(
+            "SELECT {CAPSULE_COLUMNS} FROM capsules
+              WHERE callback IS NOT NULL AND julianday(expires_at) <= julianday(?)"
+        ))
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for capsule in due.iter().cloned().map(Capsule::from) {
+            sqlx::query(
+                "INSERT INTO capsule_callbacks (key, url, deadline, payload, due_at)
+                  VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(&capsule.key)
+            .bind(&capsule.callback)
+            .bind(capsule.expires_at)
+            .bind(capsule.payload.as_ref().map(Json))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+            if capsule.every_secs.is_some() {
+                sqlx::query("UPDATE capsules SET expires_at = ? WHERE key = ?")
+                    .bind(capsule.next_deadline(now))
+                    .bind(&capsule.key)
+                    .execute(&mut *tx)
+                    .await?;
+            } else {
+                sqlx::query("DELETE FROM capsules WHERE key = ?")
+                    .bind(&capsule.key)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(due.len())
+    }
+
+    async fn claim_callbacks(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<CapsuleCallback>, AppError> {
+        let callbacks = sqlx::query_as::<_, CallbackRow>(
+            "UPDATE capsule_callbacks SET attempts = attempts + 1, due_at = ?2
+              WHERE id IN (
+                SELECT id FROM capsule_callbacks
+                  WHERE julianday(due_at) <= julianday(?1)
+                  ORDER BY julianday(due_at)
+                  LIMIT ?3
+              )
+              RETURNING id, key, url, deadline, payload, attempts",
+        )
+        .bind(now)
+        .bind(lease_until)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(callbacks.into_iter().map(CapsuleCallback::from).collect())
+    }
+
+    async fn retry_callback(&self, id: i64, due_at: DateTime<Utc>) -> Result<(), AppError> {
+        sqlx::query("UPDATE capsule_callbacks SET due_at = ? WHERE id = ?")
+            .bind(due_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn finish_callback(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM capsule_callbacks WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChatStore for SqliteStore {
+    async fn append_chat(
+        &self,
+        room: i32,
+        user: &str,
+        message: &str,
+        sent_at: DateTime<Utc>,
+        keep: i64,
+    ) -> Result<ChatMessage, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let message = sqlx::query_as::<_, ChatRow>(
+            "INSERT INTO chat_messages (room, user_name, message, sent_at) VALUES (?, ?, ?, ?)
+              RETURNING id, room, user_name, message, sent_at",
+        )
+        .bind(room)
+        .bind(user)
+        .bind(message)
+        .bind(sent_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            "DELETE FROM chat_messages
+              WHERE room = ?1 AND id <= (
+                SELECT id FROM chat_messages WHERE room = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2
+              )",
+        )
+        .bind(room)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message.into())
+    }
+
+    async fn chat_history(
+        &self,
+        room: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let messages = sqlx::query_as::<_, ChatRow>(
+            "SELECT id, room, user_name, message, sent_at FROM chat_messages
+              WHERE room = ?1 AND (?2 IS NULL OR id < ?2)
+              ORDER BY id DESC
+              LIMIT ?3",
+        )
+        .bind(room)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages.into_iter().rev().map(ChatMessage::from).collect())
+    }
+
+    async fn chat_after(
+        &self,
+        room: i32,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let messages = sqlx::query_as::<_, ChatRow>(
+            "SELECT id, room, user_name, message, sent_at FROM chat_messages
+              WHERE room = ? AND id > ?
+              ORDER BY id
+              LIMIT ?",
+        )
+        .bind(room)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages.into_iter().map(ChatMessage::from).collect())
+    }
+
+    async fn chat_message(&self, room: i32, id: i64) -> Result<Option<ChatMessage>, AppError> {
+        let message = sqlx::query_as::<_, ChatRow>(
+            "SELECT id, room, user_name, message, sent_at FROM chat_messages
+              WHERE room = ?1 AND id = ?2",
+        )
+        .bind(room)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message.map(ChatMessage::from))
+    }
+
+    async fn edit_chat(&self, room: i32, id: i64, message: &str) -> Result<(), AppError> {
+        sqlx::query("UPDATE chat_messages SET message = ?3 WHERE room = ?1 AND id = ?2")
+            .bind(room)
+            .bind(id)
+            .bind(message)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_chat(&self, room: i32, id: i64) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM chat_messages WHERE room = ?1 AND id = ?2")
+            .bind(room)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn reset_chat(&self) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM chat_messages")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn chat_views(&self) -> Result<Vec<ChatViews>, AppError> {
+        let views = sqlx::query_as::<_, (i32, String, i64)>(
+            "SELECT room, user_name, views FROM chat_views",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(views
+            .into_iter()
+            .map(|(room, user, views)| ChatViews { room, user, views })
+            .collect())
+    }
+
+    async fn add_chat_views(&self, views: &[ChatViews]) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        for added in views {
+            sqlx::query(
+                "INSERT INTO chat_views (room, user_name, views) VALUES (?1, ?2, ?3)
+                  ON CONFLICT (room, user_name) DO UPDATE SET views = views + excluded.views",
+            )
+            .bind(added.room)
+            .bind(&added.user)
+            .bind(added.views)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reset_chat_views(
+        &self,
+        room: Option<i32>,
+        user: Option<&str>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "DELETE FROM chat_views
+              WHERE (?1 IS NULL OR room = ?1) AND (?2 IS NULL OR user_name = ?2)",
+        )
+        .bind(room)
+        .bind(user)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Columns of a capsule, the payload being stored as JSON text.
+type CapsuleRow = (
+    String,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+    Option<Json<Value>>,
+    Option<i64>,
+    Option<String>,
+);
+
+impl From<CapsuleRow> for Capsule {
+    fn from((key, saved_at, expires_at, payload, every_secs, callback): CapsuleRow) -> Self {
+        Self {
+            key,
+            saved_at,
+            expires_at,
+            payload: payload.map(|Json(payload)| payload),
+            every_secs,
+            callback,
+        }
+    }
+}
+
+type CallbackRow = (i64, String, String, DateTime<Utc>, Option<Json<Value>>, i32);
+
+impl From<CallbackRow> for CapsuleCallback {
+    fn from((id, key, url, deadline, payload, attempts): CallbackRow) -> Self {
+        Self {
+            id,
+            key,
+            url,
+            deadline,
+            payload: payload.map(|Json(payload)| payload),
+            attempts,
+        }
+    }
+}
+
+type ChatRow = (i64, i32, String, String, DateTime<Utc>);
+
+impl From<ChatRow> for ChatMessage {
+    fn from((id, room, user, message, sent_at): ChatRow) -> Self {
+        Self {
+            id,
+            room,
+            user,
+            message,
+            sent_at,
+        }
+    }
+}
+
+// Runs a checked playground statement. SQLite has neither read-only
+// transactions nor statement timeouts, so the connection is made read-only
+// and a progress handler interrupts the statement past the deadline. Both are
+// undone however the statement went, and a connection they cannot be undone
+// on is closed instead of going back to the pool.
+async fn select_read_only(
+    pool: &SqlitePool,
+    sql: &str,
+    max_rows: usize,
+) -> Result<SelectOutput, AppError> {
+    let mut conn = pool.acquire().await?;
+    let output = select_limited(&mut conn, sql, max_rows).await;
+
+    if let Err(err) = unlimit(&mut conn).await {
+        if let Err(close_err) = conn.detach().close().await {
+            error!("failed to close playground connection: {close_err:?}");
+        }
+        return Err(err);
+    }
+    output
+}
+
+async fn select_limited(
+    conn: &mut SqliteConnection,
+    sql: &str,
+    max_rows: usize,
+) -> Result<SelectOutput, AppError> {
+    sqlx::query("PRAGMA query_only = ON")
+        .execute(&mut *conn)
+        .await?;
+    let deadline = Instant::now() + PLAYGROUND_TIMEOUT;
+    conn.lock_handle()
+        .await?
+        .set_progress_handler(1000, move || Instant::now() < deadline);
+
+    select(conn, sql, max_rows).await
+}
+
+async fn unlimit(conn: &mut SqliteConnection) -> Result<(), AppError> {
+    conn.lock_handle().await?.remove_progress_handler();
+    sqlx::query("PRAGMA query_only = OFF")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn select(
+    conn: &mut SqliteConnection,
+    sql: &str,
+    max_rows: usize,
+) -> Result<SelectOutput, AppError> {
+    let statement = (&mut *conn)
+        .prepare(sql)
+        .await
+        .map_err(playground::query_error)?;
+    let columns = statement
+        .columns()
+        .iter()
+        .map(|column| SelectColumn {
+            name: column.name().to_string(),
+            type_name: column.type_info().name().to_string(),
+        })
+        .collect();
+
+    let mut rows = conn
+        .fetch(sql)
+        .take(max_rows.saturating_add(1))
+        .map_err(playground::query_error)
+        .and_then(|row| async move { playground_row(&row) })
+        .try_collect::<Vec<_>>()
+        .await?;
+    let truncated = rows.len() > max_rows;
+    rows.truncate(max_rows);
+
+    Ok(SelectOutput {
+        columns,
+        rows,
+        truncated,
+    })
+}
+
+// Values of a row converted to the closest JSON type, going by what each
+// value holds since SQLite columns are not bound to a type.
+fn playground_row(row: &SqliteRow) -> Result<Vec<Value>, AppError> {
+    (0..row.len())
+        .map(|index| {
+            let value = row.try_get_raw(index)?;
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+
+            Ok(match value.type_info().name() {
+                "INTEGER" => row.try_get_unchecked::<i64, _>(index)?.into(),
+                "REAL" => row.try_get_unchecked::<f64, _>(index)?.into(),
+                "BLOB" => row
+                    .try_get_unchecked::<Vec<u8>, _>(index)?
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>()
+                    .into(),
+                _ => row.try_get_unchecked::<String, _>(index)?.into(),
+            })
+        })
+        .collect()
+}
+
+// Start of the UTC bucket an order falls in, formatted as RFC 3339. Weeks
+// start on Monday, as they do for Postgres' date_trunc.
+fn bucket_start(bucket: Bucket) -> &'static str {
+    match bucket {
+        Bucket::Day => "strftime('%Y-%m-%dT00:00:00Z', created_at)",
+        Bucket::Week => "strftime('%Y-%m-%dT00:00:00Z', created_at, 'weekday 0', '-6 days')",
+        Bucket::Month => "strftime('%Y-%m-01T00:00:00Z', created_at)",
+    }
+}
@@ -1,30 +1,233 @@
+#[cfg(feature = "day11")]
+use axum::extract::multipart::MultipartError;
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::{json, Map, Value};
+use tracing::error;
 
-// https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
-// Make our own error that wraps `anyhow::Error`.
-pub struct AppError(anyhow::Error);
+// Errors returned by the calendar handlers.
+//
+// Every variant maps to an HTTP status code and is rendered as an RFC 7807
+// `application/problem+json` document, so clients can tell their own mistakes
+// (4xx) from server faults (5xx).
+#[derive(Debug)]
+pub enum AppError {
+    // The request was malformed or failed validation (400).
+    BadRequest(String),
+    // The client is not allowed to do what it asked for (403).
+    Forbidden(String),
+    // The requested resource does not exist (404).
+    NotFound(String),
+    // The request conflicts with the current state of the resource (409).
+    Conflict(String),
+    // The request body is larger than the handler accepts (413).
+    PayloadTooLarge(String),
+    // The request body comes in a format the handler does not read (415).
+    UnsupportedMediaType(String),
+    // The client sent more requests than it is allowed to (429).
+    TooManyRequests(String),
+    // The server lacks what it takes to handle the request (501).
+    NotImplemented(String),
+    // An upstream service failed or returned something unusable (502).
+    Upstream(anyhow::Error),
+    // The database cannot be reached right now (503).
+    #[cfg(feature = "db")]
+    Database(sqlx::Error),
+    // Anything else (500).
+    Internal(anyhow::Error),
+    // Another error carrying extra problem details members.
+    Extended(Box<AppError>, Map<String, Value>),
+}
+
+impl AppError {
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::BadRequest(detail.into())
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        Self::Forbidden(detail.into())
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::NotFound(detail.into())
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        Self::Conflict(detail.into())
+    }
+
+    pub fn payload_too_large(detail: impl Into<String>) -> Self {
+        Self::PayloadTooLarge(detail.into())
+    }
+
+    pub fn unsupported_media_type(detail: impl Into<String>) -> Self {
+        Self::UnsupportedMediaType(detail.into())
+    }
+
+    pub fn too_many_requests(detail: impl Into<String>) -> Self {
+        Self::TooManyRequests(detail.into())
+    }
+
+    pub fn not_implemented(detail: impl Into<String>) -> Self {
+        Self::NotImplemented(detail.into())
+    }
+
+    pub fn upstream(err: impl Into<anyhow::Error>) -> Self {
+        Self::Upstream(err.into())
+    }
 
-// Tell axum how to convert `AppError` into a response.
+    // Adds an extension member to the problem details of this error.
+    pub fn with_extension(self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        match self {
+            Self::Extended(err, mut extensions) => {
+                extensions.insert(name.into(), value.into());
+                Self::Extended(err, extensions)
+            }
+            err => {
+                let mut extensions = Map::new();
+                extensions.insert(name.into(), value.into());
+                Self::Extended(Box::new(err), extensions)
+            }
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            #[cfg(feature = "db")]
+            Self::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Extended(err, _) => err.status(),
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Self::BadRequest(detail)
+            | Self::Forbidden(detail)
+            | Self::NotFound(detail)
+            | Self::Conflict(detail)
+            | Self::PayloadTooLarge(detail)
+            | Self::UnsupportedMediaType(detail)
+            | Self::TooManyRequests(detail)
+            | Self::NotImplemented(detail) => detail.clone(),
+            Self::Upstream(err) | Self::Internal(err) => format!("{err:#}"),
+            #[cfg(feature = "db")]
+            Self::Database(err) => err.to_string(),
+            Self::Extended(err, _) => err.detail(),
+        }
+    }
+
+    #[cfg(feature = "db")]
+    fn from_sqlx(err: sqlx::Error) -> Self {
+        use sqlx::error::ErrorKind;
+
+        match &err {
+            sqlx::Error::RowNotFound => Self::NotFound("no matching row".to_string()),
+            sqlx::Error::Database(db_err) => match db_err.kind() {
+                ErrorKind::UniqueViolation | ErrorKind::ForeignKeyViolation => {
+                    Self::Conflict(db_err.message().to_string())
+                }
+                ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                    Self::BadRequest(db_err.message().to_string())
+                }
+                // Data exceptions, SQLSTATE class 22 on Postgres (e.g. a
+                // quantity sum overflowing or a negative LIMIT) and
+                // SQLITE_MISMATCH on SQLite, come from what the client sent.
+                _ if is_data_exception(db_err.code().as_deref()) => {
+                    Self::BadRequest(db_err.message().to_string())
+                }
+                _ => Self::Internal(err.into()),
+            },
+            // The database cannot be reached or has no connection to spare.
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::Protocol(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => Self::Database(err),
+            _ => Self::Internal(err.into()),
+        }
+    }
+}
+
+#[cfg(feature = "db")]
+fn is_data_exception(code: Option<&str>) -> bool {
+    match code {
+        Some(code) if code.len() == 5 => code.starts_with("22"),
+        Some(code) => code == "20",
+        None => false,
+    }
+}
+
+// Tell axum how to convert `AppError` into a problem details response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let status = self.status();
+        let detail = self.detail();
+
+        if status.is_server_error() {
+            error!("{}: {}", status, detail);
+        }
+
+        let mut body = json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or("Unknown Error"),
+            "status": status.as_u16(),
+            "detail": detail,
+        });
+        if let Self::Extended(_, extensions) = self {
+            body.as_object_mut().unwrap().extend(extensions);
+        }
+
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
         )
             .into_response()
     }
 }
 
-// This enables using `?` on functions that return `Result<_, anyhow::Error>` to turn them into
-// `Result<_, AppError>`. That way you don't need to do that manually.
+// This enables using `?` on anything that converts into `anyhow::Error`. Errors
+// we know how to classify are turned into the matching variant, everything
+// else becomes an internal server error.
 impl<E> From<E> for AppError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        let err = err.into();
+
+        #[cfg(feature = "db")]
+        let err = match err.downcast::<sqlx::Error>() {
+            Ok(err) => return Self::from_sqlx(err),
+            Err(err) => err,
+        };
+
+        #[cfg(feature = "day11")]
+        let err = match err.downcast::<MultipartError>() {
+            Ok(err) => {
+                return match err.status() {
+                    StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(err.body_text()),
+                    status if status.is_client_error() => Self::BadRequest(err.body_text()),
+                    _ => Self::Internal(err.into()),
+                }
+            }
+            Err(err) => err,
+        };
+
+        Self::Internal(err)
     }
 }
@@ -0,0 +1,232 @@
+use std::{convert::Infallible, io};
+
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+    response::{IntoResponse, Response},
+    Json,
+};
+use csv_async::AsyncWriterBuilder;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use tracing::error;
+
+use super::error::AppError;
+
+// Names of the fields a row serializes to, in order. Delimited text starts
+// with them even when there are no rows, so the columns are known either way.
+pub trait Columns {
+    const COLUMNS: &'static [&'static str];
+}
+
+// Format a report is sent in, picked from the `Accept` header:
+//
+// - `application/json` (also for `*/*` or no header at all)
+// - `application/x-ndjson`: one JSON object per line
+// - `text/csv`: a header line naming the fields, then one row per line
+// - `text/tab-separated-values`: like CSV, separated by tabs
+//
+// The supported type with the highest quality wins, the first listed among
+// equals. Types with a quality of 0 are never picked, and JSON is sent when
+// nothing listed is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(accept) = parts.headers.get(header::ACCEPT) else {
+            return Ok(Self::Json);
+        };
+
+        let mut best = None::<(f32, Self)>;
+        for media_range in accept.to_str().unwrap_or_default().split(',') {
+            let mut params = media_range.split(';');
+            let mime = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let format = match mime.as_str() {
+                "application/json" | "application/*" | "*/*" => Self::Json,
+                "application/x-ndjson" => Self::Ndjson,
+                "text/csv" => Self::Csv,
+                "text/tab-separated-values" => Self::Tsv,
+                _ => continue,
+            };
+            let Some(quality) = quality(params) else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(best, _)| quality > best) {
+                best = Some((quality, format));
+            }
+        }
+
+        Ok(best.map_or(Self::Json, |(_, format)| format))
+    }
+}
+
+// Quality given by the `q` parameter of a media range, 1 when missing. `None`
+// when it is not a number between 0 and 1.
+fn quality<'a>(mut params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    let Some(q) = params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("q")
+            .then_some(value.trim())
+    }) else {
+        return Some(1.0);
+    };
+
+    q.parse::<f32>()
+        .ok()
+        .filter(|quality| (0.0..=1.0).contains(quality))
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Tsv => "text/tab-separated-values; charset=utf-8",
+        }
+    }
+
+    // Whether rows are sent as delimited text, which cannot hold nested
+    // values.
+    pub fn is_delimited(self) -> bool {
+        matches!(self, Self::Csv | Self::Tsv)
+    }
+
+    // Response holding all of `rows`. JSON comes out as an array, exactly
+    // like `Json` would send it.
+    pub async fn table<T: Serialize + Columns + Sync>(
+        self,
+        rows: &[T],
+    ) -> Result<Response, AppError> {
+        if self == Self::Json {
+            return Ok(Json(rows).into_response());
+        }
+
+        let mut body = self.header::<T>().await?;
+        body.extend(self.encode(rows, true).await?);
+        Ok(([(header::CONTENT_TYPE, self.content_type())], body).into_response())
+    }
+
+    // Response streaming the rows of `pages` as they come. JSON comes out as
+    // a single array. Once the response has started, a failing page can only
+    // cut the body short, which the client sees as a broken download.
+    pub fn stream<T, S>(self, pages: S) -> Response
+    where
+        T: Serialize + Columns + Send + Sync + 'static,
+        S: Stream<Item = Result<Vec<T>, AppError>> + Send + 'static,
+    {
+        let close = match self {
+            Self::Json => "]",
+            _ => "",
+        };
+
+        let rows = pages
+            .enumerate()
+            .then(move |(page, rows)| async move {
+                let rows = rows?;
+                self.encode(&rows, page == 0).await.map(Bytes::from)
+            })
+            .map_err(|err| {
+                error!("export aborted: {err:?}");
+                io::Error::other("export aborted")
+            });
+        let open = stream::once(async move {
+            match self {
+                Self::Json => Ok(Bytes::from_static(b"[")),
+                _ => self.header::<T>().await.map(Bytes::from).map_err(|err| {
+                    error!("export aborted: {err:?}");
+                    io::Error::other("export aborted")
+                }),
+            }
+        });
+        let body = open.chain(rows).chain(stream::once(async move {
+            Ok(Bytes::from_static(close.as_bytes()))
+        }));
+
+        (
+            [(header::CONTENT_TYPE, self.content_type())],
+            Body::from_stream(body),
+        )
+            .into_response()
+    }
+
+    // Header line of delimited text, nothing for JSON.
+    async fn header<T: Columns>(self) -> Result<Vec<u8>, AppError> {
+        let delimiter = match self {
+            Self::Json | Self::Ndjson => return Ok(Vec::new()),
+            Self::Csv => b',',
+            Self::Tsv => b'\t',
+        };
+
+        let mut writer = AsyncWriterBuilder::new()
+            .delimiter(delimiter)
+            .create_writer(Vec::new());
+        writer.write_record(T::COLUMNS).await?;
+        writer
+            .into_inner()
+            .await
+            .map_err(|err| AppError::Internal(anyhow::anyhow!("failed to flush header: {err}")))
+    }
+
+    // Encodes `rows`, all but the `first` of a response getting a separator
+    // from the previous JSON row.
+    async fn encode<T: Serialize + Sync>(
+        self,
+        rows: &[T],
+        first: bool,
+    ) -> Result<Vec<u8>, AppError> {
+        let delimiter = match self {
+            Self::Json => {
+                let mut body = Vec::new();
+                for (index, row) in rows.iter().enumerate() {
+                    if !first || index > 0 {
+                        body.push(b',');
+                    }
+                    serde_json::to_writer(&mut body, row)?;
+                }
+                return Ok(body);
+            }
+            Self::Ndjson => {
+                let mut body = Vec::new();
+                for row in rows {
+                    serde_json::to_writer(&mut body, row)?;
+                    body.push(b'\n');
+                }
+                return Ok(body);
+            }
+            Self::Csv => b',',
+            Self::Tsv => b'\t',
+        };
+
+        let mut writer = AsyncWriterBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .create_serializer(Vec::new());
+        for row in rows {
+            writer.serialize(row).await?;
+        }
+        writer
+            .into_inner()
+            .await
+            .map_err(|err| AppError::Internal(anyhow::anyhow!("failed to flush rows: {err}")))
+    }
+}
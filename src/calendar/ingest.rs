@@ -0,0 +1,194 @@
+use std::{error::Error as StdError, io};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    http::header,
+    response::{IntoResponse, Response},
+    Json, RequestExt,
+};
+use csv_async::{AsyncReaderBuilder, ErrorKind, Trim};
+use futures_util::{StreamExt, TryStreamExt};
+use http_body_util::LengthLimitError;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio_util::io::StreamReader;
+
+use super::error::AppError;
+
+// Malformed rows listed in a rejection, the rest are only counted.
+const MAX_REPORTED_ROWS: usize = 100;
+
+// Rows posted to an ingestion route, read according to the content type:
+//
+// - `application/json`: a JSON array of rows
+// - `text/csv`: a header line naming the fields, then one row per line
+// - `application/x-ndjson`: one JSON object per line
+//
+// CSV and NDJSON bodies are parsed as they stream in. Every malformed row is
+// reported with its line number, and a single one rejects the whole batch.
+pub struct Batch<T>(pub Vec<T>);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Batch<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let rows = match mime.as_str() {
+            "text/csv" => read_csv(body_reader(req)).await,
+            "application/x-ndjson" => read_ndjson(body_reader(req)).await,
+            "application/json" => {
+                let Json(rows) = Json::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                Ok(rows)
+            }
+            _ => Err(AppError::unsupported_media_type(
+                "expected application/json, text/csv or application/x-ndjson",
+            )),
+        };
+
+        rows.map(Batch).map_err(IntoResponse::into_response)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct RowError {
+    line: u64,
+    detail: String,
+}
+
+// Malformed rows seen so far.
+#[derive(Default)]
+struct RowErrors {
+    count: usize,
+    listed: Vec<RowError>,
+}
+
+impl RowErrors {
+    fn push(&mut self, line: u64, detail: impl Into<String>) {
+        self.count += 1;
+        if self.listed.len() < MAX_REPORTED_ROWS {
+            self.listed.push(RowError {
+                line,
+                detail: detail.into(),
+            });
+        }
+    }
+
+    fn finish<T>(self, rows: Vec<T>) -> Result<Vec<T>, AppError> {
+        let Some(first) = self.listed.first() else {
+            return Ok(rows);
+        };
+
+        let rows = if self.count == 1 { "row" } else { "rows" };
+        Err(AppError::bad_request(format!(
+            "{} malformed {rows}, the first on line {}: {}",
+            self.count, first.line, first.detail
+        ))
+        .with_extension("errors", serde_json::json!(self.listed)))
+    }
+}
+
+// The request body as a byte stream, honouring the route's body limit.
+fn body_reader(req: Request) -> impl AsyncRead + Unpin + Send {
+    let stream = req
+        .into_limited_body()
+        .into_data_stream()
+        .map_err(io::Error::other);
+    StreamReader::new(stream)
+}
+
+// Error for a body that could not be read to the end.
+fn read_error(err: io::Error) -> AppError {
+    let mut source = err.get_ref().map(|err| err as &(dyn StdError + 'static));
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return AppError::payload_too_large("request body is too large");
+        }
+        source = err.source();
+    }
+
+    AppError::bad_request(format!("failed to read request body: {err}"))
+}
+
+async fn read_csv<T: DeserializeOwned>(
+    reader: impl AsyncRead + Unpin + Send,
+) -> Result<Vec<T>, AppError> {
+    let mut reader = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .create_deserializer(reader);
+    let mut records = reader.deserialize_with_pos::<T>();
+
+    let mut rows = Vec::new();
+    let mut errors = RowErrors::default();
+    while let Some((record, position)) = records.next().await {
+        match record {
+            Ok(row) => rows.push(row),
+            Err(err) if err.is_io_error() => {
+                let ErrorKind::Io(err) = err.into_kind() else {
+                    unreachable!()
+                };
+                return Err(read_error(err));
+            }
+            Err(err) => {
+                let detail = match err.kind() {
+                    ErrorKind::Deserialize { err, .. } => err.to_string(),
+                    ErrorKind::UnequalLengths {
+                        expected_len, len, ..
+                    } => format!("expected {expected_len} fields, found {len}"),
+                    _ => err.to_string(),
+                };
+                errors.push(position.line(), detail);
+            }
+        }
+    }
+
+    errors.finish(rows)
+}
+
+async fn read_ndjson<T: DeserializeOwned>(
+    reader: impl AsyncRead + Unpin + Send,
+) -> Result<Vec<T>, AppError> {
+    let mut lines = BufReader::new(reader).lines();
+
+    let mut rows = Vec::new();
+    let mut errors = RowErrors::default();
+    let mut line = 0;
+    while let Some(text) = lines.next_line().await.map_err(read_error)? {
+        line += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&text) {
+            Ok(row) => rows.push(row),
+            Err(err) => {
+                // The position serde reports is relative to this line.
+                let detail = err.to_string();
+                let detail = detail
+                    .strip_suffix(&format!(" at line {} column {}", err.line(), err.column()))
+                    .unwrap_or(&detail);
+                errors.push(line, format!("{detail} at column {}", err.column()));
+            }
+        }
+    }
+
+    errors.finish(rows)
+}
@@ -0,0 +1,149 @@
+use axum::Router;
+
+pub mod calendar;
+#[cfg(feature = "db")]
+use calendar::db::Store;
+
+// Builds the router serving every enabled calendar day, with the default
+// options. The background work of the days, like delivering capsule callbacks
+// or writing chat view counts to the store, is spawned on the current Tokio
+// runtime and runs for as long as the runtime does, so this has to be called
+// from within one.
+//
+// Each day is behind a cargo feature of the same name; the store is only
+// needed when one of the days storing time capsules, orders or chat messages
+// (12, 13, 18, 19) is enabled.
+#[cfg_attr(
+    not(any(feature = "day12", feature = "day19")),
+    allow(unused_variables)
+)]
+pub fn app(#[cfg(feature = "db")] store: Store) -> Router {
+    #[cfg(feature = "db")]
+    let (router, workers) = app_with_workers(store, Options::default());
+    #[cfg(not(feature = "db"))]
+    let (router, workers) = app_with_workers(Options::default());
+
+    #[cfg(any(feature = "day12", feature = "day19"))]
+    tokio::spawn(workers.run(std::future::pending()));
+
+    router
+}
+
+// Settings of the days having any, each defaulting to what the challenge
+// needs.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    #[cfg(feature = "day19")]
+    pub chat: calendar::day19::ChatOptions,
+}
+
+// Same as `app` with the given options, handing back the work going on
+// besides answering requests rather than spawning it. Shared by the Shuttle
+// entry point in `main.rs` and the standalone binary in `bin/standalone.rs`,
+// which stop the workers along with the server. Building either starts
+// nothing; the workers only run once `Workers::run` is awaited.
+#[cfg_attr(not(feature = "day19"), allow(unused_variables))]
+pub fn app_with_workers(
+    #[cfg(feature = "db")] store: Store,
+    options: Options,
+) -> (Router, Workers) {
+    let router = stateless_days();
+    #[cfg_attr(not(any(feature = "day12", feature = "day19")), allow(unused_mut))]
+    let mut workers = Workers::default();
+
+    #[cfg(feature = "day12")]
+    let router = {
+        workers.callbacks = Some((calendar::day12::Scheduler::default(), store.clone()));
+        router.nest("/12", calendar::day12::task(store.clone()))
+    };
+    #[cfg(feature = "day13")]
+    let router = router.nest("/13", calendar::day13::task(store.clone()));
+    #[cfg(feature = "day18")]
+    let router = router.nest("/18", calendar::day18::task(store.clone()));
+    #[cfg(feature = "day19")]
+    let router = {
+        let (chat, views) = calendar::day19::task_with_options(store.clone(), options.chat);
+        workers.views = views;
+        router.nest("/19", chat)
+    };
+
+    (router, workers)
+}
+
+// Days answering without the store.
+fn stateless_days() -> Router {
+    let router = Router::new();
+
+    #[cfg(feature = "day_1")]
+    let router = router.nest("/", calendar::day_1::task());
+    #[cfg(feature = "day1")]
+    let router = router.nest("/1", calendar::day1::task());
+    #[cfg(feature = "day4")]
+    let router = router.nest("/4", calendar::day4::task());
+    #[cfg(feature = "day5")]
+    let router = router.nest("/5", calendar::day5::task());
+    #[cfg(feature = "day6")]
+    let router = router.nest("/6", calendar::day6::task());
+    #[cfg(feature = "day7")]
+    let router = router.nest("/7", calendar::day7::task());
+    #[cfg(feature = "day8")]
+    let router = router.nest("/8", calendar::day8::task());
+    #[cfg(feature = "day11")]
+    let router = router.nest("/11", calendar::day11::task());
+    #[cfg(feature = "day14")]
+    let router = router.nest("/14", calendar::day14::task());
+    #[cfg(feature = "day15")]
+    let router = router.nest("/15", calendar::day15::task());
+    #[cfg(feature = "day20")]
+    let router = router.nest("/20", calendar::day20::task());
+    #[cfg(feature = "day21")]
+    let router = router.nest("/21", calendar::day21::task());
+    #[cfg(feature = "day22")]
+    let router = router.nest("/22", calendar::day22::task());
+
+    router
+}
+
+// Background work of the enabled days, like delivering capsule callbacks or
+// keeping chat view counts in the store.
+#[derive(Default)]
+pub struct Workers {
+    #[cfg(feature = "day12")]
+    callbacks: Option<(calendar::day12::Scheduler, Store)>,
+    #[cfg(feature = "day19")]
+    views: Option<calendar::day19::ViewsFlusher>,
+}
+
+impl Workers {
+    // Runs every worker until `shutdown` resolves, then waits for them to
+    // wrap up what they are doing.
+    pub async fn run(self, shutdown: impl std::future::Future<Output = ()>) {
+        let (stop, stopped) = tokio::sync::watch::channel(false);
+        #[cfg_attr(
+            not(any(feature = "day12", feature = "day19")),
+            allow(unused_variables)
+        )]
+        let stopped = move || {
+            let mut stopped = stopped.clone();
+            async move {
+                // A dropped sender stops the workers just as well.
+                let _ = stopped.wait_for(|&stopped| stopped).await;
+            }
+        };
+
+        #[cfg_attr(not(any(feature = "day12", feature = "day19")), allow(unused_mut))]
+        let mut workers = tokio::task::JoinSet::<()>::new();
+        #[cfg(feature = "day12")]
+        if let Some((scheduler, store)) = self.callbacks {
+            workers.spawn(scheduler.run(store, stopped()));
+        }
+        #[cfg(feature = "day19")]
+        if let Some(views) = self.views {
+            workers.spawn(views.run(stopped()));
+        }
+
+        shutdown.await;
+        let _ = stop.send(true);
+        while workers.join_next().await.is_some() {}
+    }
+}
@@ -0,0 +1,201 @@
+// Day 12 capsule callbacks, delivered to a stand-in receiver over a real
+// socket.
+
+#![cfg(feature = "day12")]
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Method, Request, StatusCode},
+    response::Redirect,
+    routing::post,
+    Json, Router,
+};
+use cch23_santa5276::calendar::{
+    day12::{self, Scheduler},
+    db,
+};
+use chrono::{SecondsFormat, TimeDelta, Utc};
+use serde_json::{json, Value};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+// Receiver keeping every callback body. The first attempt for capsule
+// "flaky" fails, so it has to be retried. `/moved` sends callbacks on to
+// `/hook`.
+async fn receiver() -> (String, Received) {
+    let received = Received::default();
+    let router = Router::new()
+        .route("/moved", post(|| async { Redirect::temporary("/hook") }))
+        .route(
+            "/hook",
+            post(
+                |State(received): State<Received>, Json(body): Json<Value>| async move {
+                    let fail = body["key"] == "flaky" && body["attempt"] == 1;
+                    received.lock().unwrap().push(body);
+                    if fail {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                },
+            ),
+        )
+        .with_state(received.clone());
+
+    let url = format!("http://{}/hook", common::serve(router).await);
+    (url, received)
+}
+
+async fn capsule_app(url: &str, allowed_hosts: &[&str]) -> Router {
+    let store = db::connect(url).await.unwrap();
+    let scheduler = Scheduler {
+        poll_interval: Duration::from_millis(50),
+        retry_delay: Duration::from_millis(100),
+        max_attempts: 3,
+        timeout: Duration::from_secs(1),
+        allowed_hosts: allowed_hosts.iter().map(|host| host.to_string()).collect(),
+    };
+    tokio::spawn(scheduler.run(store.clone(), std::future::pending()));
+    Router::new().nest("/12", day12::task(store))
+}
+
+async fn request(router: &Router, method: Method, uri: &str) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = common::send(router, request).await;
+    (status, String::from_utf8(body).unwrap())
+}
+
+// Bodies received for capsule `key`, once there are `count` of them.
+async fn wait_for(received: &Received, key: &str, count: usize) -> Vec<Value> {
+    for _ in 0..100 {
+        let bodies = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|body| body["key"] == key)
+            .cloned()
+            .collect::<Vec<_>>();
+        if bodies.len() >= count {
+            return bodies;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("expected {count} callbacks for capsule {key:?}");
+}
+
+async fn check_callbacks(url: &str) {
+    let (hook, received) = receiver().await;
+    let router = capsule_app(url, &["127.0.0.1"]).await;
+
+    let deadline =
+        (Utc::now() + TimeDelta::milliseconds(300)).to_rfc3339_opts(SecondsFormat::Millis, true);
+    let (status, _) = request(
+        &router,
+        Method::POST,
+        &format!("/12/save/flaky?deadline={deadline}&callback={hook}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(
+        &router,
+        Method::POST,
+        &format!("/12/save/recurring?every=1&callback={hook}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let flaky = wait_for(&received, "flaky", 2).await;
+    assert_eq!(
+        flaky,
+        [1, 2].map(|attempt| json!({
+            "key": "flaky",
+            "deadline": deadline,
+            "attempt": attempt,
+            "payload": null,
+        }))
+    );
+    let (status, _) = request(&router, Method::GET, "/12/load/flaky").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Each deadline of a recurring capsule is called back once, a period
+    // after the one before.
+    let recurring = wait_for(&received, "recurring", 2).await;
+    let deadlines = recurring
+        .iter()
+        .map(|body| body["deadline"].as_str().unwrap().parse().unwrap())
+        .collect::<Vec<chrono::DateTime<Utc>>>();
+    assert_eq!(deadlines[1] - deadlines[0], TimeDelta::seconds(1));
+    let (status, body) = request(&router, Method::GET, "/12/capsules/recurring").await;
+    assert_eq!(status, StatusCode::OK);
+    let capsule = serde_json::from_str::<Value>(&body).unwrap();
+    assert_eq!(capsule["expires_in"], 1);
+}
+
+#[tokio::test]
+async fn callbacks_memory() {
+    check_callbacks("memory").await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn callbacks_sqlite() {
+    check_callbacks("sqlite::memory:").await;
+}
+
+// The receiver is on this machine, so it is only called back when allowed,
+// and never through a redirect.
+#[tokio::test]
+async fn callbacks_are_refused_for_private_hosts_and_redirects() {
+    let (hook, received) = receiver().await;
+    let private = capsule_app("memory", &[]).await;
+    let allowed = capsule_app("memory", &["127.0.0.1"]).await;
+    let deadline =
+        (Utc::now() + TimeDelta::milliseconds(100)).to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    let (status, _) = request(
+        &private,
+        Method::POST,
+        &format!("/12/save/private?deadline={deadline}&callback={hook}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let moved = hook.replace("/hook", "/moved");
+    let (status, _) = request(
+        &allowed,
+        Method::POST,
+        &format!("/12/save/moved?deadline={deadline}&callback={moved}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(
+        &allowed,
+        Method::POST,
+        &format!("/12/save/allowed?deadline={deadline}&callback={hook}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // By the time the allowed one arrives, the redirected one has been tried
+    // as often as it gets.
+    wait_for(&received, "allowed", 1).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let keys = received
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|body| body["key"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["allowed"]);
+}
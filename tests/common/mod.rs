@@ -0,0 +1,173 @@
+// Helpers shared by the integration tests.
+//
+// Golden fixtures live in `tests/fixtures/<name>.json` as a list of request and
+// expected response pairs which are replayed in order against one router, so
+// later cases can observe the state left by earlier ones. Run the tests with
+// `UPDATE_GOLDEN=1` to rewrite the expected responses from the actual ones.
+
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Case {
+    pub name: String,
+    pub request: GoldenRequest,
+    pub response: GoldenResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GoldenRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<Value>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct GoldenResponse {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<Value>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.json"))
+}
+
+// Router with every enabled day, storing orders in process memory.
+pub fn app() -> Router {
+    #[cfg(feature = "db")]
+    return cch23_santa5276::app(std::sync::Arc::new(
+        cch23_santa5276::calendar::db::MemoryStore::default(),
+    ));
+    #[cfg(not(feature = "db"))]
+    return cch23_santa5276::app();
+}
+
+pub async fn send(
+    router: &Router,
+    request: Request<Body>,
+) -> (StatusCode, Option<String>, Vec<u8>) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, content_type, body.to_vec())
+}
+
+fn build_request(request: &GoldenRequest) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(Method::from_bytes(request.method.as_bytes()).unwrap())
+        .uri(&request.uri);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+
+    if let Some(json) = &request.json {
+        builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json.to_string()))
+            .unwrap()
+    } else {
+        builder
+            .body(Body::from(request.body.clone().unwrap_or_default()))
+            .unwrap()
+    }
+}
+
+fn golden_response(
+    status: StatusCode,
+    content_type: Option<String>,
+    body: Vec<u8>,
+) -> GoldenResponse {
+    let body = String::from_utf8(body).expect("response body is not UTF-8");
+    let is_json = content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.contains("json"));
+
+    match serde_json::from_str::<Value>(&body) {
+        Ok(json) if is_json => GoldenResponse {
+            status: status.as_u16(),
+            content_type,
+            body: None,
+            json: Some(json),
+        },
+        _ => GoldenResponse {
+            status: status.as_u16(),
+            content_type: content_type.filter(|_| !body.is_empty()),
+            body: Some(body).filter(|body| !body.is_empty()),
+            json: None,
+        },
+    }
+}
+
+// Replays the fixture `name` against `router`, failing on the first response
+// that differs from the recorded one.
+pub async fn check_golden(router: Router, name: &str) {
+    let path = fixture_path(name);
+    let fixture = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    let mut cases: Vec<Case> = serde_json::from_str(&fixture).unwrap();
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+
+    for case in &mut cases {
+        let (status, content_type, body) = send(&router, build_request(&case.request)).await;
+        let actual = golden_response(status, content_type, body);
+
+        if update {
+            case.response = actual;
+        } else {
+            assert_eq!(actual, case.response, "{name}: {}", case.name);
+        }
+    }
+
+    if update {
+        let fixture = serde_json::to_string_pretty(&cases).unwrap();
+        fs::write(&path, fixture + "\n").unwrap();
+    }
+}
+
+// Serves `router` on an ephemeral local port, for clients that need a real
+// socket such as websockets or the PokeAPI stand-in.
+pub async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("127.0.0.1:{}", addr.port())
+}
+
+// Router with every enabled day, storing orders in the store behind `url`.
+#[cfg(feature = "db")]
+pub async fn app_with_store(url: &str) -> Router {
+    let store = cch23_santa5276::calendar::db::connect(url).await.unwrap();
+    cch23_santa5276::app(store)
+}
@@ -0,0 +1,157 @@
+// Replays the golden fixtures in `tests/fixtures` against the router.
+
+mod common;
+
+#[allow(unused_imports)]
+use common::{app, check_golden};
+
+#[cfg(feature = "day_1")]
+#[tokio::test]
+async fn day_1() {
+    check_golden(app(), "day_1").await;
+}
+
+#[cfg(feature = "day1")]
+#[tokio::test]
+async fn day1() {
+    check_golden(app(), "day1").await;
+}
+
+#[cfg(feature = "day4")]
+#[tokio::test]
+async fn day4() {
+    check_golden(app(), "day4").await;
+}
+
+#[cfg(feature = "day5")]
+#[tokio::test]
+async fn day5() {
+    check_golden(app(), "day5").await;
+}
+
+#[cfg(feature = "day6")]
+#[tokio::test]
+async fn day6() {
+    check_golden(app(), "day6").await;
+}
+
+#[cfg(feature = "day7")]
+#[tokio::test]
+async fn day7() {
+    check_golden(app(), "day7").await;
+}
+
+#[cfg(feature = "day8")]
+#[tokio::test]
+async fn day8() {
+    use axum::{extract::Path, http::StatusCode, routing::get, Json, Router};
+    use serde_json::json;
+
+    // Stand-in for the PokeAPI, covering a hit, a miss, a failure and a
+    // response without a weight.
+    let pokeapi = Router::new().route(
+        "/api/v2/pokemon/:id",
+        get(|Path(id): Path<u32>| async move {
+            match id {
+                25 => Ok(Json(json!({ "name": "pikachu", "weight": 60 }))),
+                404 => Err(StatusCode::NOT_FOUND),
+                500 => Err(StatusCode::INTERNAL_SERVER_ERROR),
+                _ => Ok(Json(json!({ "name": "missingno" }))),
+            }
+        }),
+    );
+    let pokeapi_url = format!("http://{}", common::serve(pokeapi).await);
+
+    let router = Router::new().nest(
+        "/8",
+        cch23_santa5276::calendar::day8::task_with_api(pokeapi_url),
+    );
+    check_golden(router, "day8").await;
+}
+
+#[cfg(feature = "day12")]
+#[tokio::test]
+async fn day12() {
+    check_golden(app(), "day12").await;
+}
+
+#[cfg(all(feature = "day12", feature = "sqlite"))]
+#[tokio::test]
+async fn day12_sqlite() {
+    check_golden(common::app_with_store("sqlite::memory:").await, "day12").await;
+}
+
+#[cfg(feature = "day13")]
+#[tokio::test]
+async fn day13() {
+    check_golden(app(), "day13").await;
+}
+
+#[cfg(all(feature = "day13", feature = "sqlite"))]
+#[tokio::test]
+async fn day13_sqlite() {
+    check_golden(common::app_with_store("sqlite::memory:").await, "day13").await;
+}
+
+#[cfg(all(feature = "day13", feature = "sqlite"))]
+#[tokio::test]
+async fn day13_sql_sqlite() {
+    let router = common::app_with_store("sqlite::memory:").await;
+    check_golden(router.clone(), "day13_sql").await;
+    check_golden(router, "day13_sql_sqlite").await;
+}
+
+#[cfg(feature = "day14")]
+#[tokio::test]
+async fn day14() {
+    check_golden(app(), "day14").await;
+}
+
+#[cfg(feature = "day15")]
+#[tokio::test]
+async fn day15() {
+    check_golden(app(), "day15").await;
+}
+
+#[cfg(feature = "day18")]
+#[tokio::test]
+async fn day18() {
+    check_golden(app(), "day18").await;
+}
+
+#[cfg(all(feature = "day18", feature = "sqlite"))]
+#[tokio::test]
+async fn day18_sqlite() {
+    check_golden(common::app_with_store("sqlite::memory:").await, "day18").await;
+}
+
+// Needs a scratch Postgres database in `TEST_DATABASE_URL`; its capsules,
+// orders and regions tables are wiped.
+#[cfg(all(feature = "day12", feature = "day13", feature = "day18"))]
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn store_postgres() {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+
+    // The day 13 reset keeps the regions other tests may have left behind.
+    let store = cch23_santa5276::calendar::db::connect(&url).await.unwrap();
+    store.reset_all().await.unwrap();
+    let router = cch23_santa5276::app(store);
+    check_golden(router.clone(), "day12").await;
+    check_golden(router.clone(), "day13").await;
+    check_golden(router.clone(), "day13_sql").await;
+    check_golden(router.clone(), "day13_sql_postgres").await;
+    check_golden(router, "day18").await;
+}
+
+#[cfg(feature = "day21")]
+#[tokio::test]
+async fn day21() {
+    check_golden(app(), "day21").await;
+}
+
+#[cfg(feature = "day22")]
+#[tokio::test]
+async fn day22() {
+    check_golden(app(), "day22").await;
+}
@@ -0,0 +1,111 @@
+// Store behaviour the routes cannot show in a fixture, like concurrent
+// batches, run against every backend.
+
+#![cfg(feature = "db")]
+
+use cch23_santa5276::calendar::db::{self, Gift, IngestParams, OnConflict, Order, Region, Store};
+
+fn orders(ids: std::ops::RangeInclusive<i32>) -> Vec<Order> {
+    ids.map(|id| Order {
+        id,
+        region_id: 1,
+        gift_name: "Ball".to_string(),
+        quantity: 1,
+        created_at: None,
+    })
+    .collect()
+}
+
+async fn store(url: &str) -> Store {
+    let store = db::connect(url).await.unwrap();
+    store.reset_all().await.unwrap();
+    let region = Region {
+        id: 1,
+        name: "North Pole".to_string(),
+        parent_id: None,
+    };
+    store
+        .insert_regions(&[region], OnConflict::Error)
+        .await
+        .unwrap();
+    store
+}
+
+// Batches racing over the same ids each skip what the others wrote.
+async fn concurrent_skips(store: Store) {
+    let batches = (0..8)
+        .map(|batch| {
+            let store = store.clone();
+            let orders = orders(batch * 5 + 1..=batch * 5 + 40);
+            tokio::spawn(async move {
+                let params = IngestParams {
+                    on_conflict: OnConflict::Skip,
+                    ..Default::default()
+                };
+                store.insert_orders(&orders, &params).await
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut inserted = 0;
+    for batch in batches {
+        let report = batch.await.unwrap().unwrap();
+        assert_eq!(report.inserted + report.skipped, 40);
+        inserted += report.inserted;
+    }
+    assert_eq!(inserted, 75);
+}
+
+// Replacing an order gives back the stock it reserved, even when the order
+// replacing it reserves none.
+async fn replaced_reservations(store: Store) {
+    let ball = Gift {
+        name: "Ball".to_string(),
+        stock: 10,
+        low_stock_threshold: 0,
+    };
+    store.create_gift(&ball).await.unwrap();
+    let stock = || async { store.gift("Ball").await.unwrap().unwrap().stock };
+
+    let reserve = IngestParams {
+        reserve_stock: true,
+        ..Default::default()
+    };
+    store.insert_orders(&orders(1..=3), &reserve).await.unwrap();
+    assert_eq!(stock().await, 7);
+
+    let replace = IngestParams {
+        on_conflict: OnConflict::Replace,
+        ..Default::default()
+    };
+    store.insert_orders(&orders(1..=3), &replace).await.unwrap();
+    assert_eq!(stock().await, 10);
+
+    // Nothing is left to give back the second time.
+    store.insert_orders(&orders(1..=3), &replace).await.unwrap();
+    assert_eq!(stock().await, 10);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn memory() {
+    concurrent_skips(store("memory").await).await;
+    replaced_reservations(store("memory").await).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test(flavor = "multi_thread")]
+async fn sqlite() {
+    concurrent_skips(store("sqlite::memory:").await).await;
+    replaced_reservations(store("sqlite::memory:").await).await;
+}
+
+// Needs a scratch Postgres database in `TEST_DATABASE_URL`; its orders,
+// regions and gifts tables are wiped.
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn postgres() {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+
+    concurrent_skips(store(&url).await).await;
+    replaced_reservations(store(&url).await).await;
+}
@@ -0,0 +1,161 @@
+// Routes taking binary uploads: images for day 11, tarballs for day 20.
+
+mod common;
+
+#[allow(unused_imports)]
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+#[allow(unused_imports)]
+use common::{app, send};
+
+#[cfg(feature = "day11")]
+fn multipart(files: &[&[u8]]) -> Request<Body> {
+    const BOUNDARY: &str = "cch23-boundary";
+
+    let mut body = Vec::new();
+    for file in files {
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\nContent-Type: image/png\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(file);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+    Request::post("/11/red_pixels")
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[cfg(feature = "day11")]
+fn png(pixels: &[[u8; 3]]) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(pixels.len() as u32, 1, |x, _| {
+        image::Rgb(pixels[x as usize])
+    });
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .unwrap();
+    png.into_inner()
+}
+
+#[cfg(feature = "day11")]
+#[tokio::test]
+async fn day11_red_pixels() {
+    let first = png(&[[255, 0, 0], [200, 100, 99], [100, 100, 100]]);
+    let second = png(&[[10, 0, 5], [0, 0, 255]]);
+
+    let (status, _, body) = send(&app(), multipart(&[&first, &second])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"3");
+}
+
+#[cfg(feature = "day11")]
+#[tokio::test]
+async fn day11_rejects_non_images() {
+    let (status, content_type, _) = send(&app(), multipart(&[b"not an image"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(content_type.as_deref(), Some("application/problem+json"));
+}
+
+#[cfg(feature = "day20")]
+fn post(uri: &str, body: Vec<u8>) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[cfg(feature = "day20")]
+fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, *content).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[cfg(feature = "day20")]
+#[tokio::test]
+async fn day20_archive_files() {
+    let archive = tarball(&[("a.txt", b"hello"), ("dir/b.txt", b"0123456789")]);
+    let router = app();
+
+    let (status, _, body) = send(&router, post("/20/archive_files", archive.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"2");
+
+    let (status, _, body) = send(&router, post("/20/archive_files_size", archive)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"15");
+}
+
+#[cfg(feature = "day20")]
+#[tokio::test]
+async fn day20_cookie() {
+    use git2::{Repository, Signature};
+
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    // Commits on the christmas branch, newest last. Only the first one has a
+    // cookie in santa.txt.
+    let commits = [
+        ("Grinch", "nothing here"),
+        ("Santa", "a COOKIE for you"),
+        ("Elf", "crumbs"),
+    ];
+    let mut parent = None;
+    let mut cookie_commit = None;
+    for (author, santa) in commits {
+        std::fs::write(dir.path().join("santa.txt"), santa).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("santa.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now(author, "north@pole").unwrap();
+        let parents = parent.iter().collect::<Vec<_>>();
+        let id = repo
+            .commit(None, &signature, &signature, author, &tree, &parents)
+            .unwrap();
+        if santa.contains("COOKIE") {
+            cookie_commit = Some(id);
+        }
+        parent = Some(repo.find_commit(id).unwrap());
+    }
+    repo.branch("christmas", parent.as_ref().unwrap(), false)
+        .unwrap();
+
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append_dir_all(".", dir.path()).unwrap();
+    let archive = builder.into_inner().unwrap();
+
+    let (status, _, body) = send(&app(), post("/20/cookie", archive)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        String::from_utf8(body).unwrap(),
+        format!("Santa {}", cookie_commit.unwrap())
+    );
+}
+
+#[cfg(feature = "day20")]
+#[tokio::test]
+async fn day20_cookie_without_repository() {
+    let archive = tarball(&[("santa.txt", b"COOKIE")]);
+
+    let (status, content_type, _) = send(&app(), post("/20/cookie", archive)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(content_type.as_deref(), Some("application/problem+json"));
+}
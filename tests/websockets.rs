@@ -0,0 +1,763 @@
+// Day 19 websockets, driven over a real socket.
+
+#![cfg(feature = "day19")]
+
+mod common;
+
+use std::time::Duration;
+
+use cch23_santa5276::calendar::{
+    day19::{self, ChatOptions, RateLimit},
+    db,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{protocol::frame::coding::CloseCode, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(addr: &str, path: &str) -> Socket {
+    connect_async(format!("ws://{addr}{path}")).await.unwrap().0
+}
+
+async fn next_text(socket: &mut Socket) -> Option<String> {
+    match timeout(Duration::from_millis(300), socket.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => Some(text),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn ping_pong_after_serve() {
+    let addr = common::serve(common::app()).await;
+    let mut socket = connect(&addr, "/19/ws/ping").await;
+
+    socket.send(Message::text("ping")).await.unwrap();
+    assert_eq!(next_text(&mut socket).await, None);
+
+    socket.send(Message::text("serve")).await.unwrap();
+    socket.send(Message::text("ping")).await.unwrap();
+    assert_eq!(next_text(&mut socket).await.as_deref(), Some("pong"));
+}
+
+#[tokio::test]
+async fn tweets_reach_the_room_and_count_views() {
+    let addr = common::serve(common::app()).await;
+    let client = reqwest::Client::new();
+    client
+        .post(format!("http://{addr}/19/reset"))
+        .send()
+        .await
+        .unwrap();
+
+    let mut alice = connect(&addr, "/19/ws/room/1/user/alice").await;
+    let mut bob = connect(&addr, "/19/ws/room/1/user/bob").await;
+    let mut carol = connect(&addr, "/19/ws/room/2/user/carol").await;
+    // Give the server a moment to subscribe everyone to their room.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    alice
+        .send(Message::text(json!({ "message": "hello" }).to_string()))
+        .await
+        .unwrap();
+    // Too long, dropped by the server.
+    alice
+        .send(Message::text(
+            json!({ "message": "x".repeat(129) }).to_string(),
+        ))
+        .await
+        .unwrap();
+
+    let expected = json!({ "user": "alice", "message": "hello" });
+    for socket in [&mut alice, &mut bob] {
+        let tweet: Value = serde_json::from_str(&next_text(socket).await.unwrap()).unwrap();
+        assert_eq!(tweet, expected);
+        assert_eq!(next_text(socket).await, None);
+    }
+    assert_eq!(next_text(&mut carol).await, None);
+
+    let views = client
+        .get(format!("http://{addr}/19/views"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(views, "2");
+}
+
+// Day 19 alone, keeping the newest `history_size` messages of each room.
+async fn chat_app(url: &str, history_size: usize) -> axum::Router {
+    let store = db::connect(url).await.unwrap();
+    chat_router(store, history_size)
+}
+
+fn chat_router(store: db::Store, history_size: usize) -> axum::Router {
+    let options = ChatOptions {
+        history_size,
+        flush_interval: Some(Duration::from_millis(50)),
+        ..ChatOptions::default()
+    };
+    let (router, views) = day19::task_with_options(store, options);
+    tokio::spawn(views.unwrap().run(std::future::pending()));
+    axum::Router::new().nest("/19", router)
+}
+
+async fn get_json(client: &reqwest::Client, url: String) -> (u16, Value) {
+    let response = client.get(url).send().await.unwrap();
+    let status = response.status().as_u16();
+    (
+        status,
+        serde_json::from_str(&response.text().await.unwrap()).unwrap(),
+    )
+}
+
+fn said(page: &Value) -> Vec<&str> {
+    page["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["message"].as_str().unwrap())
+        .collect()
+}
+
+async fn check_history(url: &str) {
+    let addr = common::serve(chat_app(url, 3).await).await;
+    let client = reqwest::Client::new();
+    client
+        .post(format!("http://{addr}/19/reset"))
+        .send()
+        .await
+        .unwrap();
+
+    let mut alice = connect(&addr, "/19/ws/room/1/user/alice").await;
+    for i in 1..=4 {
+        let message = json!({ "message": format!("hello {i}") });
+        alice
+            .send(Message::text(message.to_string()))
+            .await
+            .unwrap();
+        // Wait for the echo, so the messages are stored in order.
+        next_text(&mut alice).await.unwrap();
+    }
+
+    // Only the newest three are kept and replayed, without counting as views.
+    let mut bob = connect(&addr, "/19/ws/room/1/user/bob").await;
+    for i in 2..=4 {
+        let tweet: Value = serde_json::from_str(&next_text(&mut bob).await.unwrap()).unwrap();
+        assert_eq!(
+            tweet,
+            json!({ "user": "alice", "message": format!("hello {i}") })
+        );
+    }
+    assert_eq!(next_text(&mut bob).await, None);
+    let views = client
+        .get(format!("http://{addr}/19/views"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(views, "4");
+
+    let (status, page) =
+        get_json(&client, format!("http://{addr}/19/rooms/1/history?limit=2")).await;
+    assert_eq!(status, 200);
+    assert_eq!(said(&page), ["hello 3", "hello 4"]);
+    assert_eq!(page["messages"][0]["user"], "alice");
+
+    let next = page["next"].as_i64().unwrap();
+    let (_, page) = get_json(
+        &client,
+        format!("http://{addr}/19/rooms/1/history?limit=2&before={next}"),
+    )
+    .await;
+    assert_eq!(said(&page), ["hello 2"]);
+    assert_eq!(page["next"], Value::Null);
+
+    let (status, _) = get_json(&client, format!("http://{addr}/19/rooms/1/history?limit=0")).await;
+    assert_eq!(status, 400);
+    let (_, page) = get_json(&client, format!("http://{addr}/19/rooms/2/history")).await;
+    assert_eq!(page, json!({ "messages": [], "next": null }));
+
+    client
+        .post(format!("http://{addr}/19/reset"))
+        .send()
+        .await
+        .unwrap();
+    let (_, page) = get_json(&client, format!("http://{addr}/19/rooms/1/history")).await;
+    assert_eq!(said(&page), Vec::<&str>::new());
+}
+
+#[tokio::test]
+async fn history_is_replayed_and_paged() {
+    check_history("memory").await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn history_is_replayed_and_paged_sqlite() {
+    check_history("sqlite::memory:").await;
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn history_is_replayed_and_paged_postgres() {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    check_history(&url).await;
+}
+
+// A room without history still keeps the message just sent.
+#[tokio::test]
+async fn history_keeps_at_least_the_last_message() {
+    let addr = common::serve(chat_app("memory", 0).await).await;
+    let client = reqwest::Client::new();
+
+    let mut alice = connect(&addr, "/19/ws/room/1/user/alice").await;
+    for i in 1..=2 {
+        let message = json!({ "message": format!("hello {i}") });
+        alice
+            .send(Message::text(message.to_string()))
+            .await
+            .unwrap();
+        next_text(&mut alice).await.unwrap();
+    }
+
+    let (_, page) = get_json(&client, format!("http://{addr}/19/rooms/1/history")).await;
+    assert_eq!(said(&page), ["hello 2"]);
+}
+
+async fn next_json(socket: &mut Socket) -> Value {
+    serde_json::from_str(&next_text(socket).await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn presence_is_tracked_and_announced() {
+    let addr = common::serve(common::app()).await;
+    let client = reqwest::Client::new();
+
+    let mut alice = connect(&addr, "/19/ws/room/5/user/alice?events=true").await;
+    // Give the server a moment to let everyone in, in order.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut bob = connect(&addr, "/19/ws/room/5/user/bob").await;
+    let mut bob_again = connect(&addr, "/19/ws/room/5/user/bob").await;
+    let mut carol = connect(&addr, "/19/ws/room/6/user/carol").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        next_json(&mut alice).await,
+        json!({ "event": "join", "user": "bob" })
+    );
+    assert_eq!(next_text(&mut alice).await, None);
+
+    let (_, rooms) = get_json(&client, format!("http://{addr}/19/rooms")).await;
+    assert_eq!(
+        rooms,
+        json!([{ "room": 5, "users": 2 }, { "room": 6, "users": 1 }])
+    );
+    let (_, users) = get_json(&client, format!("http://{addr}/19/rooms/5/users")).await;
+    assert_eq!(users, json!(["alice", "bob"]));
+
+    // Only those who asked for events hear about typing.
+    bob.send(Message::text(json!({ "typing": true }).to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        next_json(&mut alice).await,
+        json!({ "event": "typing", "user": "bob", "typing": true })
+    );
+    assert_eq!(next_text(&mut bob_again).await, None);
+
+    // Bob is still there while connected once more.
+    bob.close(None).await.unwrap();
+    assert_eq!(next_text(&mut alice).await, None);
+    bob_again.close(None).await.unwrap();
+    assert_eq!(
+        next_json(&mut alice).await,
+        json!({ "event": "leave", "user": "bob" })
+    );
+
+    // Rooms are gone once everyone left.
+    carol.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, rooms) = get_json(&client, format!("http://{addr}/19/rooms")).await;
+    assert_eq!(rooms, json!([{ "room": 5, "users": 1 }]));
+    let (_, users) = get_json(&client, format!("http://{addr}/19/rooms/6/users")).await;
+    assert_eq!(users, json!([]));
+}
+
+// Next frame of the envelope protocol, without its timestamp.
+async fn next_envelope(socket: &mut Socket) -> Value {
+    let mut envelope = next_json(socket).await;
+    assert_eq!(envelope["v"], 1);
+    assert!(envelope["ts"].is_string());
+    let object = envelope.as_object_mut().unwrap();
+    object.remove("v");
+    object.remove("ts");
+    envelope
+}
+
+#[tokio::test]
+async fn envelopes_carry_edits_deletes_reactions_and_errors() {
+    let addr = common::serve(common::app()).await;
+    let client = reqwest::Client::new();
+
+    let mut alice = connect(&addr, "/19/ws/room/7/user/alice?v=1").await;
+    let mut bob = connect(&addr, "/19/ws/room/7/user/bob?v=1").await;
+    let mut plain = connect(&addr, "/19/ws/room/7/user/carol").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Quotes and backslashes come through as they were sent.
+    let message = r#"say "hi" \o/"#;
+    alice
+        .send(Message::text(
+            json!({ "type": "tweet", "message": message }).to_string(),
+        ))
+        .await
+        .unwrap();
+    let tweet = next_envelope(&mut bob).await;
+    let id = tweet["id"].as_str().unwrap().to_string();
+    assert_eq!(id.len(), 26);
+    assert_eq!(
+        tweet,
+        json!({ "type": "tweet", "id": id, "room": 7, "user": "alice", "message": message })
+    );
+    assert_eq!(next_envelope(&mut alice).await, tweet);
+    assert_eq!(
+        next_json(&mut plain).await,
+        json!({ "user": "alice", "message": message })
+    );
+
+    // Only the author may change a tweet, and errors only go back to whoever
+    // caused them. Trying more often than the offence limit allows does not
+    // get bob disconnected, as no rule of the room was broken.
+    for _ in 0..4 {
+        bob.send(Message::text(
+            json!({ "type": "edit", "id": id, "message": "mine now" }).to_string(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            next_envelope(&mut bob).await,
+            json!({
+                "type": "error",
+                "room": 7,
+                "status": 403,
+                "error": format!("tweet {id} can only be changed by alice"),
+            })
+        );
+    }
+    assert_eq!(next_text(&mut alice).await, None);
+
+    bob.send(Message::text(
+        json!({ "type": "reaction", "id": id, "reaction": "+1" }).to_string(),
+    ))
+    .await
+    .unwrap();
+    alice
+        .send(Message::text(
+            json!({ "type": "edit", "id": id, "message": "edited" }).to_string(),
+        ))
+        .await
+        .unwrap();
+    for socket in [&mut alice, &mut bob] {
+        assert_eq!(
+            next_envelope(socket).await,
+            json!({ "type": "reaction", "id": id, "room": 7, "user": "bob", "reaction": "+1" })
+        );
+        assert_eq!(
+            next_envelope(socket).await,
+            json!({ "type": "edit", "id": id, "room": 7, "user": "alice", "message": "edited" })
+        );
+    }
+    let (_, page) = get_json(&client, format!("http://{addr}/19/rooms/7/history")).await;
+    assert_eq!(said(&page), ["edited"]);
+    assert_eq!(page["messages"][0]["id"], id);
+
+    alice
+        .send(Message::text(
+            json!({ "type": "delete", "id": id }).to_string(),
+        ))
+        .await
+        .unwrap();
+    for socket in [&mut alice, &mut bob] {
+        assert_eq!(
+            next_envelope(socket).await,
+            json!({ "type": "delete", "id": id, "room": 7, "user": "alice" })
+        );
+    }
+    let (_, page) = get_json(&client, format!("http://{addr}/19/rooms/7/history")).await;
+    assert_eq!(said(&page), Vec::<&str>::new());
+
+    for (frame, status, error) in [
+        (
+            json!({ "type": "delete", "id": id }),
+            404,
+            format!("no tweet {id} in room 7"),
+        ),
+        (
+            json!({ "type": "tweet", "message": "x".repeat(129) }),
+            413,
+            "Message length cannot be over 128".to_string(),
+        ),
+    ] {
+        alice.send(Message::text(frame.to_string())).await.unwrap();
+        assert_eq!(
+            next_envelope(&mut alice).await,
+            json!({ "type": "error", "room": 7, "status": status, "error": error })
+        );
+    }
+    alice.send(Message::text("not json")).await.unwrap();
+    let error = next_envelope(&mut alice).await;
+    assert_eq!(error["status"], 400);
+
+    // Updates and errors are not sent to clients speaking plain frames.
+    plain
+        .send(Message::text(
+            json!({ "message": "x".repeat(129) }).to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(next_text(&mut plain).await, None);
+
+    let response = client
+        .get(format!("http://{addr}/19/ws/room/7/user/dave?v=2"))
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+async fn views(client: &reqwest::Client, addr: &str, query: &str) -> u64 {
+    let url = format!("http://{addr}/19/views{query}");
+    let text = client.get(url).send().await.unwrap().text().await.unwrap();
+    text.parse().unwrap()
+}
+
+async fn check_views(url: &str) {
+    let store = db::connect(url).await.unwrap();
+    let addr = common::serve(chat_router(store.clone(), 10)).await;
+    let client = reqwest::Client::new();
+
+    let mut alice = connect(&addr, "/19/ws/room/1/user/alice").await;
+    let mut bob = connect(&addr, "/19/ws/room/1/user/bob").await;
+    let mut bob_elsewhere = connect(&addr, "/19/ws/room/2/user/bob").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for message in ["one", "two"] {
+        alice
+            .send(Message::text(json!({ "message": message }).to_string()))
+            .await
+            .unwrap();
+    }
+    bob_elsewhere
+        .send(Message::text(json!({ "message": "three" }).to_string()))
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        next_text(&mut alice).await.unwrap();
+        next_text(&mut bob).await.unwrap();
+    }
+    next_text(&mut bob_elsewhere).await.unwrap();
+
+    for (query, expected) in [
+        ("", 5),
+        ("?room=1", 4),
+        ("?room=2", 1),
+        ("?user=bob", 3),
+        ("?room=1&user=alice", 2),
+        ("?room=3", 0),
+    ] {
+        assert_eq!(views(&client, &addr, query).await, expected, "{query}");
+    }
+
+    // Counts are flushed to the store and read back by the next router.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let addr = common::serve(chat_router(store.clone(), 10)).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(views(&client, &addr, "?user=bob").await, 3);
+
+    client
+        .post(format!("http://{addr}/19/reset?room=1&user=bob"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(views(&client, &addr, "").await, 3);
+    assert_eq!(views(&client, &addr, "?user=bob").await, 1);
+    client
+        .post(format!("http://{addr}/19/reset?room=2"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(views(&client, &addr, "").await, 2);
+
+    // Resets reach the store too.
+    let addr = common::serve(chat_router(store, 10)).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(views(&client, &addr, "").await, 2);
+    client
+        .post(format!("http://{addr}/19/reset"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(views(&client, &addr, "").await, 0);
+}
+
+#[tokio::test]
+async fn views_are_counted_per_room_and_user() {
+    check_views("memory").await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn views_are_counted_per_room_and_user_sqlite() {
+    check_views("sqlite::memory:").await;
+}
+
+// The router of `app` runs its own flusher, which starts by reading the
+// counts kept in the store.
+#[tokio::test]
+async fn app_keeps_views_in_the_store() {
+    let store = db::connect("memory").await.unwrap();
+    let stored = db::ChatViews {
+        room: 1,
+        user: "alice".to_string(),
+        views: 7,
+    };
+    store.add_chat_views(&[stored]).await.unwrap();
+
+    let addr = common::serve(cch23_santa5276::app(store)).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(views(&reqwest::Client::new(), &addr, "").await, 7);
+}
+
+async fn tweet(socket: &mut Socket, message: &str) {
+    let frame = json!({ "type": "tweet", "message": message });
+    socket.send(Message::text(frame.to_string())).await.unwrap();
+}
+
+// Reason the server gave for closing the connection, if it did.
+async fn close_reason(socket: &mut Socket) -> Option<String> {
+    match timeout(Duration::from_millis(300), socket.next()).await {
+        Ok(Some(Ok(Message::Close(Some(frame))))) => {
+            assert_eq!(frame.code, CloseCode::Policy);
+            Some(frame.reason.into_owned())
+        }
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn floods_are_limited_and_closed() {
+    let store = db::connect("memory").await.unwrap();
+    let options = ChatOptions {
+        rate_limit: Some(RateLimit {
+            per_second: 1.0,
+            burst: 2,
+        }),
+        max_offences: 2,
+        ..ChatOptions::default()
+    };
+    let router = axum::Router::new().nest("/19", day19::task_with_options(store, options).0);
+    let addr = common::serve(router).await;
+
+    let mut alice = connect(&addr, "/19/ws/room/1/user/alice?v=1").await;
+    for i in 1..=2 {
+        tweet(&mut alice, &format!("flood {i}")).await;
+        assert_eq!(
+            next_envelope(&mut alice).await["message"],
+            format!("flood {i}")
+        );
+    }
+
+    // The bucket is empty, and takes a second to refill.
+    for i in 3..=4 {
+        tweet(&mut alice, &format!("flood {i}")).await;
+    }
+    let too_many = json!({
+        "type": "error",
+        "room": 1,
+        "status": 429,
+        "error": "too many frames, slow down",
+    });
+    assert_eq!(next_envelope(&mut alice).await, too_many);
+    assert_eq!(next_envelope(&mut alice).await, too_many);
+    assert_eq!(
+        next_envelope(&mut alice).await,
+        json!({ "type": "error", "room": 1, "status": 403, "error": "closed after 2 offences" })
+    );
+    assert_eq!(
+        close_reason(&mut alice).await.as_deref(),
+        Some("closed after 2 offences")
+    );
+}
+
+#[tokio::test]
+async fn rooms_can_be_slowed_down_and_users_muted_or_kicked() {
+    let store = db::connect("memory").await.unwrap();
+    let options = ChatOptions {
+        admin_token: Some("s3cret".to_string()),
+        ..ChatOptions::default()
+    };
+    let router = axum::Router::new().nest("/19", day19::task_with_options(store, options).0);
+    let addr = common::serve(router).await;
+    let client = reqwest::Client::new();
+    let moderate = |path: &str, token: Option<&str>| {
+        let request = client.post(format!("http://{addr}/19/rooms/8{path}"));
+        let request = match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        async move { request.send().await.unwrap().status().as_u16() }
+    };
+    let admin = |path: &str| moderate(path, Some("s3cret"));
+
+    // Only those knowing the admin token moderate.
+    assert_eq!(moderate("/slow_mode?seconds=60", None).await, 403);
+    assert_eq!(moderate("/users/bob/kick", Some("s3cre")).await, 403);
+    assert_eq!(moderate("/users/bob/mute", Some("s3crex")).await, 403);
+
+    let mut alice = connect(&addr, "/19/ws/room/8/user/alice?v=1").await;
+    let mut bob = connect(&addr, "/19/ws/room/8/user/bob?v=1").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Slow mode holds back every user's next tweet, but not their first,
+    // which a tweet turned down does not count as.
+    assert_eq!(admin("/slow_mode?seconds=60").await, 200);
+    tweet(&mut alice, &"long ".repeat(30)).await;
+    assert_eq!(next_envelope(&mut alice).await["status"], 413);
+    tweet(&mut alice, "first").await;
+    assert_eq!(next_envelope(&mut alice).await["message"], "first");
+    assert_eq!(next_envelope(&mut bob).await["message"], "first");
+    tweet(&mut alice, "second").await;
+    let error = next_envelope(&mut alice).await;
+    assert_eq!(error["status"], 429);
+    assert!(error["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("room 8 is in slow mode, wait "));
+    assert_eq!(next_text(&mut bob).await, None);
+    assert_eq!(admin("/slow_mode?seconds=0").await, 200);
+    tweet(&mut alice, "third").await;
+    assert_eq!(next_envelope(&mut bob).await["message"], "third");
+    next_envelope(&mut alice).await;
+
+    // Muted users only listen.
+    assert_eq!(admin("/users/bob/mute?seconds=60").await, 200);
+    assert_eq!(admin("/users/bob/mute?seconds=0").await, 400);
+    tweet(&mut bob, "let me speak").await;
+    let error = next_envelope(&mut bob).await;
+    assert_eq!(error["status"], 403);
+    assert!(error["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("muted in room 8 until "));
+    assert_eq!(next_text(&mut alice).await, None);
+    assert_eq!(admin("/users/bob/unmute").await, 200);
+    tweet(&mut bob, "thanks").await;
+    assert_eq!(next_envelope(&mut alice).await["message"], "thanks");
+    next_envelope(&mut bob).await;
+
+    // Kicked users are told and disconnected, and only them.
+    assert_eq!(admin("/users/bob/kick").await, 200);
+    assert_eq!(
+        next_envelope(&mut bob).await,
+        json!({ "type": "error", "room": 8, "status": 403, "error": "kicked from room 8" })
+    );
+    assert_eq!(
+        close_reason(&mut bob).await.as_deref(),
+        Some("kicked from room 8")
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(admin("/users/bob/kick").await, 404);
+    let (_, users) = get_json(&client, format!("http://{addr}/19/rooms/8/users")).await;
+    assert_eq!(users, json!(["alice"]));
+
+    // The rules outlast everyone leaving the room, and kicked users who come
+    // back only listen for a while.
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(admin("/users/carol/mute").await, 200);
+    let mut bob = connect(&addr, "/19/ws/room/8/user/bob?v=1").await;
+    for said in ["first", "third", "thanks"] {
+        assert_eq!(next_envelope(&mut bob).await["message"], said);
+    }
+    tweet(&mut bob, "back").await;
+    let error = next_envelope(&mut bob).await;
+    assert_eq!(error["status"], 403);
+    assert!(error["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("muted in room 8 until "));
+    assert_eq!(admin("/users/bob/unmute").await, 200);
+    tweet(&mut bob, "back").await;
+    assert_eq!(next_envelope(&mut bob).await["message"], "back");
+    let mut carol = connect(&addr, "/19/ws/room/8/user/carol?v=1").await;
+    while next_text(&mut carol).await.is_some() {}
+    tweet(&mut carol, "hi").await;
+    assert_eq!(next_envelope(&mut carol).await["status"], 403);
+}
+
+// Slow mode against the store at `url`, which for a race to show up should
+// take a while to store a tweet, like Postgres does.
+async fn check_slow_mode_race(url: &str) {
+    let store = db::connect(url).await.unwrap();
+    let options = ChatOptions {
+        admin_token: Some("s3cret".to_string()),
+        ..ChatOptions::default()
+    };
+    let router = axum::Router::new().nest("/19", day19::task_with_options(store, options).0);
+    let addr = common::serve(router).await;
+
+    let mut alice = connect(&addr, "/19/ws/room/9/user/alice?v=1").await;
+    let mut bobs = Vec::new();
+    for _ in 0..8 {
+        bobs.push(connect(&addr, "/19/ws/room/9/user/bob?v=1").await);
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/19/rooms/9/slow_mode?seconds=60"))
+        .bearer_auth("s3cret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Tweeting from every connection at once gets only one tweet out.
+    futures_util::future::join_all(bobs.iter_mut().map(|bob| tweet(bob, "me first"))).await;
+    assert_eq!(next_envelope(&mut alice).await["message"], "me first");
+    assert_eq!(next_text(&mut alice).await, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_mode_holds_back_every_connection_of_a_user() {
+    check_slow_mode_race("memory").await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn slow_mode_holds_back_every_connection_of_a_user_postgres() {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    check_slow_mode_race(&url).await;
+}
+
+#[tokio::test]
+async fn moderation_is_off_without_an_admin_token() {
+    let addr = common::serve(common::app()).await;
+    let response = reqwest::Client::new()
+        .post(format!("http://{addr}/19/rooms/1/slow_mode?seconds=1"))
+        .bearer_auth("")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+}